@@ -0,0 +1,208 @@
+//! Checkout throughput under concurrency, against a real PostgreSQL server:
+//!
+//! ```sh
+//! BENCH_PG_URL=postgresql://postgres@localhost/postgres cargo bench --features pgsql
+//! ```
+//!
+//! Every task repeatedly takes a connection from one of several pools and gives it back, so
+//! the numbers reflect pool lookup, checkout and return rather than query latency. The last
+//! run checks out connections while another pool is being created.
+//!
+//! Each run is made twice: against `DbManager`, and against a baseline reproducing the registry
+//! it replaced (one tokio mutex over every pool, held while a pool opens its connections, a
+//! tokio mutex per pool and connections returned from a spawned task).
+use domner_tech_sql_client::pool_manager::{DbClient, DbManager};
+use domner_tech_sql_client::types::sql::pgsql;
+use std::{
+  collections::HashMap,
+  sync::Arc,
+  time::{Duration, Instant},
+};
+use tokio::sync::Mutex;
+
+const POOLS: usize = 4;
+const POOL_SIZE: u32 = 16;
+const ITERATIONS: usize = 200_000;
+
+#[tokio::main(flavor = "multi_thread")]
+async fn main() -> anyhow::Result<()> {
+  let Ok(url) = std::env::var("BENCH_PG_URL") else {
+    println!("BENCH_PG_URL is not set, skipping");
+    return Ok(());
+  };
+  for registry in [
+    Registry::Baseline(Baseline::default()),
+    Registry::Current(DbManager::new()),
+  ] {
+    run(&registry, &url).await?;
+    registry.close().await?;
+    // Let the server notice the closed sessions before the next run opens its own
+    tokio::time::sleep(Duration::from_millis(500)).await;
+  }
+  Ok(())
+}
+
+async fn run(registry: &Registry, url: &str) -> anyhow::Result<()> {
+  let label = registry.label();
+  for pool in 0..POOLS {
+    registry
+      .init_pool(&format!("pool{}", pool), url, POOL_SIZE)
+      .await?;
+  }
+
+  for tasks in [1, 8, 32, 64] {
+    let start = Instant::now();
+    let handles: Vec<_> = (0..tasks)
+      .map(|task| {
+        let registry = registry.clone();
+        let pool = format!("pool{}", task % POOLS);
+        tokio::spawn(async move {
+          let (mut ok, mut empty) = (0u64, 0u64);
+          for _ in 0..ITERATIONS / tasks {
+            if registry.checkout(&pool).await {
+              ok += 1;
+            } else {
+              // More tasks than connections: count the miss and try again
+              empty += 1;
+            }
+          }
+          (ok, empty)
+        })
+      })
+      .collect();
+    let (mut ok, mut empty) = (0, 0);
+    for handle in handles {
+      let (o, e) = handle.await?;
+      ok += o;
+      empty += e;
+    }
+    let elapsed = start.elapsed();
+    println!(
+      "{:>8} {:>4} tasks: {:>9.0} checkouts/s ({} ok, {} pool empty) in {:?}",
+      label,
+      tasks,
+      (ok + empty) as f64 / elapsed.as_secs_f64(),
+      ok,
+      empty,
+      elapsed
+    );
+  }
+
+  // Checkouts while another pool opens its connections
+  let init = {
+    let registry = registry.clone();
+    let url = url.to_string();
+    tokio::spawn(async move { registry.init_pool("late", &url, POOL_SIZE).await })
+  };
+  let (mut checkouts, mut slowest) = (0u64, Duration::ZERO);
+  while !init.is_finished() {
+    let start = Instant::now();
+    registry.checkout("pool0").await;
+    slowest = slowest.max(start.elapsed());
+    checkouts += 1;
+    tokio::task::yield_now().await;
+  }
+  init.await??;
+  println!(
+    "{:>8} during init_pool: {} checkouts, slowest {:?}",
+    label, checkouts, slowest
+  );
+  Ok(())
+}
+
+#[derive(Clone)]
+enum Registry {
+  Current(DbManager),
+  Baseline(Baseline),
+}
+
+impl Registry {
+  fn label(&self) -> &'static str {
+    match self {
+      Registry::Current(_) => "current",
+      Registry::Baseline(_) => "baseline",
+    }
+  }
+
+  async fn init_pool(&self, pool: &str, url: &str, size: u32) -> anyhow::Result<()> {
+    match self {
+      Registry::Current(manager) => manager.init_pool(pool, url, size).await.map(drop),
+      Registry::Baseline(baseline) => baseline.init_pool(pool, url, size).await,
+    }
+  }
+
+  /// Take a connection and give it back, holding it across a scheduling point as real work
+  /// would. False if the pool was empty.
+  async fn checkout(&self, pool: &str) -> bool {
+    match self {
+      Registry::Current(manager) => match manager.get_client(pool).await {
+        Ok(client) => {
+          tokio::task::yield_now().await;
+          drop(client);
+          true
+        }
+        Err(_) => false,
+      },
+      Registry::Baseline(baseline) => match baseline.get_client(pool).await {
+        Some(client) => {
+          tokio::task::yield_now().await;
+          drop(client);
+          true
+        }
+        None => false,
+      },
+    }
+  }
+
+  async fn close(&self) -> anyhow::Result<()> {
+    match self {
+      Registry::Current(manager) => manager.close_all().await,
+      Registry::Baseline(baseline) => {
+        baseline.pools.lock().await.clear();
+        Ok(())
+      }
+    }
+  }
+}
+
+type BaselinePool = Arc<Mutex<Vec<DbClient>>>;
+
+#[derive(Clone, Default)]
+struct Baseline {
+  pools: Arc<Mutex<HashMap<String, BaselinePool>>>,
+}
+
+impl Baseline {
+  async fn init_pool(&self, pool: &str, url: &str, size: u32) -> anyhow::Result<()> {
+    let mut pools = self.pools.lock().await;
+    let mut clients = Vec::with_capacity(size as usize);
+    for _ in 0..size {
+      let (client, connection) = pgsql::connect(url, pgsql::NoTls).await?;
+      tokio::spawn(connection);
+      clients.push(DbClient::Pgsql(client));
+    }
+    pools.insert(pool.to_string(), Arc::new(Mutex::new(clients)));
+    Ok(())
+  }
+
+  async fn get_client(&self, pool: &str) -> Option<BaselineClient> {
+    let pool = self.pools.lock().await.get(pool)?.clone();
+    let client = pool.lock().await.pop()?;
+    Some(BaselineClient {
+      pool,
+      client: Some(client),
+    })
+  }
+}
+
+struct BaselineClient {
+  pool: BaselinePool,
+  client: Option<DbClient>,
+}
+
+impl Drop for BaselineClient {
+  fn drop(&mut self) {
+    let (pool, client) = (self.pool.clone(), self.client.take());
+    tokio::spawn(async move { pool.lock().await.extend(client) });
+  }
+}
@@ -0,0 +1,329 @@
+use anyhow::Result;
+use std::{collections::BTreeMap, path::Path};
+use toml::{Table, Value};
+
+use super::PoolConfig;
+
+/// Fields that always hold text, so env values like `PASSWORD=1234` are not read as numbers.
+const TEXT_FIELDS: [&str; 15] = [
+  "url",
+  "backend",
+  "host",
+  "instance_name",
+  "database",
+  "user",
+  "password",
+  "application_name",
+  "ca_cert",
+  "mode",
+  "root_cert",
+  "client_cert",
+  "client_key",
+  "file",
+  "env",
+];
+
+/// Named pools declared in a TOML or YAML file:
+///
+/// ```toml
+/// [pools.orders]
+/// url = "postgresql://app@db.example.com/orders"
+/// max_size = 20
+/// command_timeout = "30s"
+///
+/// [pools.reports]
+/// backend = "mssql"
+/// host = "sql.example.com"
+/// database = "reports"
+/// retry_policy = { max_attempts = 3 }
+/// ```
+///
+/// Environment variables named `DB__<POOL>__<FIELD>` override or add values of the pools in the
+/// file, with `__` separating nested fields (`DB__ORDERS__PASSWORD`,
+/// `DB__REPORTS__MSSQL_TLS__CA_CERT`). Variables naming another pool are ignored. A YAML `null`
+/// leaves the field unset.
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ManagerConfig {
+  #[serde(default)]
+  pub pools: BTreeMap<String, PoolConfig>,
+}
+
+impl ManagerConfig {
+  /// Read a `.toml`, `.yaml` or `.yml` file and apply the `DB__` environment overlay
+  pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+    Self::load_with_env_prefix(path, "DB")
+  }
+
+  /// Like [`load`](Self::load), with `<prefix>__<POOL>__<FIELD>` environment variables
+  pub fn load_with_env_prefix(path: impl AsRef<Path>, prefix: &str) -> Result<Self> {
+    let path = path.as_ref();
+    let contents = std::fs::read_to_string(path)
+      .map_err(|e| anyhow::anyhow!("Failed to read {}: {}", path.display(), e))?;
+    let mut tree = match path.extension().and_then(|ext| ext.to_str()) {
+      Some("toml") => parse_toml(&contents)?,
+      Some("yaml" | "yml") => parse_yaml(&contents)?,
+      _ => {
+        return Err(anyhow::anyhow!(
+          "Unsupported config file {}, expected .toml, .yaml or .yml",
+          path.display()
+        ));
+      }
+    };
+    // Variables that are not valid Unicode cannot be ours; `std::env::vars` would panic on them
+    let vars = std::env::vars_os()
+      .filter_map(|(name, value)| Some((name.into_string().ok()?, value.into_string().ok()?)));
+    apply_env(&mut tree, prefix, vars)?;
+    Self::from_tree(tree)
+  }
+
+  pub fn from_toml(contents: &str) -> Result<Self> {
+    Self::from_tree(parse_toml(contents)?)
+  }
+
+  pub fn from_yaml(contents: &str) -> Result<Self> {
+    Self::from_tree(parse_yaml(contents)?)
+  }
+
+  fn from_tree(tree: Table) -> Result<Self> {
+    Value::Table(tree)
+      .try_into()
+      .map_err(|e| anyhow::anyhow!("Invalid pool configuration: {}", e))
+  }
+}
+
+fn parse_toml(contents: &str) -> Result<Table> {
+  contents
+    .parse()
+    .map_err(|e| anyhow::anyhow!("Invalid TOML: {}", e))
+}
+
+fn parse_yaml(contents: &str) -> Result<Table> {
+  let mut value: serde_yaml::Value =
+    serde_yaml::from_str(contents).map_err(|e| anyhow::anyhow!("Invalid YAML: {}", e))?;
+  drop_nulls(&mut value);
+  serde_yaml::from_value(value).map_err(|e| anyhow::anyhow!("Invalid YAML: {}", e))
+}
+
+/// Remove `key: null` entries, which TOML values cannot hold, so they read as absent fields
+fn drop_nulls(value: &mut serde_yaml::Value) {
+  match value {
+    serde_yaml::Value::Mapping(mapping) => {
+      mapping.retain(|_, value| !value.is_null());
+      mapping.values_mut().for_each(drop_nulls);
+    }
+    serde_yaml::Value::Sequence(items) => items.iter_mut().for_each(drop_nulls),
+    _ => {}
+  }
+}
+
+/// Merge `<prefix>__<POOL>__<FIELD>[__<FIELD>...]` variables into the pools declared in `pools`,
+/// ignoring variables of other pools
+fn apply_env(
+  tree: &mut Table,
+  prefix: &str,
+  vars: impl Iterator<Item = (String, String)>,
+) -> Result<()> {
+  let prefix = format!("{}__", prefix);
+  for (name, value) in vars {
+    let Some(path) = name.strip_prefix(&prefix) else {
+      continue;
+    };
+    let path: Vec<String> = path.split("__").map(str::to_ascii_lowercase).collect();
+    let [pool, fields @ ..] = path.as_slice() else {
+      continue;
+    };
+    let pools = table_entry(tree, "pools", &name)?;
+    // Pool names are matched case-insensitively, environment variables being upper case.
+    // Other applications may share the prefix, so variables of undeclared pools are not ours.
+    let Some(pool) = pools
+      .keys()
+      .find(|key| key.eq_ignore_ascii_case(pool))
+      .cloned()
+    else {
+      #[cfg(feature = "tracing")]
+      tracing::warn!(variable = %name, "ignoring environment variable of an undeclared pool");
+      continue;
+    };
+    if fields.is_empty() || fields.iter().any(String::is_empty) {
+      return Err(anyhow::anyhow!("Invalid environment variable `{}`", name));
+    }
+
+    let mut table = table_entry(pools, &pool, &name)?;
+    let (field, parents) = fields.split_last().expect("checked above");
+    for parent in parents {
+      table = table_entry(table, parent, &name)?;
+    }
+    let text = TEXT_FIELDS.contains(&field.as_str()) || parents.iter().any(|p| p == "params");
+    table.insert(field.clone(), env_value(value, text));
+  }
+  Ok(())
+}
+
+/// Nested table under `key`, created if missing
+fn table_entry<'t>(table: &'t mut Table, key: &str, var: &str) -> Result<&'t mut Table> {
+  table
+    .entry(key)
+    .or_insert_with(|| Value::Table(Table::new()))
+    .as_table_mut()
+    .ok_or_else(|| anyhow::anyhow!("`{}` overrides `{}`, which is not a table", var, key))
+}
+
+/// Read an environment value as the scalar it looks like, unless the field is text
+fn env_value(value: String, text: bool) -> Value {
+  if text {
+    return Value::String(value);
+  }
+  if let Ok(b) = value.parse() {
+    Value::Boolean(b)
+  } else if let Ok(i) = value.parse() {
+    Value::Integer(i)
+  } else if let Ok(f) = value.parse() {
+    Value::Float(f)
+  } else {
+    Value::String(value)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const FILE: &str = r#"
+    [pools.orders]
+    url = "postgresql://app@db.example.com/orders"
+    max_size = 5
+  "#;
+
+  fn overlay(vars: &[(&str, &str)]) -> Result<Table> {
+    let mut tree = parse_toml(FILE)?;
+    let vars = vars.iter().map(|(k, v)| (k.to_string(), v.to_string()));
+    apply_env(&mut tree, "DB", vars)?;
+    Ok(tree)
+  }
+
+  fn pool<'t>(tree: &'t Table, name: &str) -> &'t Table {
+    tree["pools"][name].as_table().unwrap()
+  }
+
+  #[test]
+  fn overrides_and_adds_fields() {
+    let tree = overlay(&[
+      ("DB__ORDERS__MAX_SIZE", "20"),
+      ("DB__ORDERS__PASSWORD", "s3cret"),
+    ])
+    .unwrap();
+    let orders = pool(&tree, "orders");
+    assert_eq!(orders["max_size"], Value::Integer(20));
+    assert_eq!(orders["password"], Value::String("s3cret".into()));
+    assert_eq!(
+      orders["url"],
+      Value::String("postgresql://app@db.example.com/orders".into())
+    );
+  }
+
+  #[test]
+  fn nested_fields() {
+    let tree = overlay(&[
+      ("DB__ORDERS__PG_TLS__ROOT_CERT", "/etc/ca.pem"),
+      ("DB__ORDERS__RETRY_POLICY__MAX_ATTEMPTS", "3"),
+    ])
+    .unwrap();
+    let orders = pool(&tree, "orders");
+    assert_eq!(
+      orders["pg_tls"]["root_cert"],
+      Value::String("/etc/ca.pem".into())
+    );
+    assert_eq!(orders["retry_policy"]["max_attempts"], Value::Integer(3));
+
+    let config = ManagerConfig::from_tree(tree).unwrap();
+    assert_eq!(config.pools["orders"].options.retry_policy.max_attempts, 3);
+  }
+
+  #[test]
+  fn variables_of_undeclared_pools_are_ignored() {
+    let tree = overlay(&[
+      ("DB__REPORTS__BACKEND", "mssql"),
+      ("DB__CACHE", "redis://cache"),
+      ("DB__OTHER____HOST", "x"),
+    ])
+    .unwrap();
+    assert_eq!(tree["pools"].as_table().unwrap().len(), 1);
+    assert!(ManagerConfig::from_tree(tree).is_ok());
+  }
+
+  #[test]
+  fn yaml_nulls_are_absent_fields() {
+    let tree = parse_yaml(
+      "pools:\n  orders:\n    url: postgresql://app@db/orders\n    password: null\n    \
+       pg_tls: ~\n",
+    )
+    .unwrap();
+    let config = ManagerConfig::from_tree(tree).unwrap();
+    assert_eq!(config.pools["orders"].password, None);
+    assert!(parse_yaml("pools:\n").is_ok());
+  }
+
+  #[test]
+  fn only_prefixed_variables_apply() {
+    let tree = overlay(&[
+      ("APP__ORDERS__MAX_SIZE", "20"),
+      ("DBX__ORDERS__MAX_SIZE", "20"),
+      ("DB_ORDERS__MAX_SIZE", "20"),
+      ("PATH", "/usr/bin"),
+    ])
+    .unwrap();
+    assert_eq!(pool(&tree, "orders")["max_size"], Value::Integer(5));
+    assert_eq!(tree["pools"].as_table().unwrap().len(), 1);
+  }
+
+  #[test]
+  fn pool_names_match_case_insensitively() {
+    let mut tree = parse_toml("[pools.Orders]\nmax_size = 5").unwrap();
+    let vars = [("DB__ORDERS__MAX_SIZE".to_string(), "7".to_string())];
+    apply_env(&mut tree, "DB", vars.into_iter()).unwrap();
+    assert_eq!(pool(&tree, "Orders")["max_size"], Value::Integer(7));
+    assert_eq!(tree["pools"].as_table().unwrap().len(), 1);
+  }
+
+  #[test]
+  fn values_are_coerced_unless_the_field_is_text() {
+    let tree = overlay(&[
+      ("DB__ORDERS__MIN_SIZE", "2"),
+      ("DB__ORDERS__MULTI_SUBNET_FAILOVER", "true"),
+      ("DB__ORDERS__COMMAND_TIMEOUT", "1.5"),
+      ("DB__ORDERS__CONNECT_TIMEOUT", "5s"),
+      ("DB__ORDERS__PASSWORD", "1234"),
+      ("DB__ORDERS__USER", "true"),
+      ("DB__ORDERS__PARAMS__OPTIONS", "42"),
+    ])
+    .unwrap();
+    let orders = pool(&tree, "orders");
+    assert_eq!(orders["min_size"], Value::Integer(2));
+    assert_eq!(orders["multi_subnet_failover"], Value::Boolean(true));
+    assert_eq!(orders["command_timeout"], Value::Float(1.5));
+    assert_eq!(orders["connect_timeout"], Value::String("5s".into()));
+    assert_eq!(orders["password"], Value::String("1234".into()));
+    assert_eq!(orders["user"], Value::String("true".into()));
+    assert_eq!(orders["params"]["options"], Value::String("42".into()));
+
+    let config = ManagerConfig::from_tree(tree).unwrap();
+    let orders = &config.pools["orders"];
+    assert_eq!(orders.password.as_deref(), Some("1234"));
+    assert_eq!(
+      orders.options.command_timeout,
+      Some(std::time::Duration::from_millis(1500))
+    );
+    assert_eq!(
+      orders.connect_timeout,
+      Some(std::time::Duration::from_secs(5))
+    );
+  }
+
+  #[test]
+  fn invalid_variables_are_reported() {
+    assert!(overlay(&[("DB__ORDERS", "x")]).is_err());
+    assert!(overlay(&[("DB__ORDERS____PASSWORD", "x")]).is_err());
+    assert!(overlay(&[("DB__ORDERS__URL__HOST", "x")]).is_err());
+  }
+}
@@ -0,0 +1,602 @@
+use anyhow::Result;
+use std::{borrow::Cow, fmt, str::FromStr, sync::Arc, time::Duration};
+
+use crate::credentials::CredentialProvider;
+use crate::hooks::{HookFuture, PoolHooks};
+use crate::pool_manager::{DbClient, PoolOptions};
+use crate::retry::RetryPolicy;
+use crate::tls::{MssqlTlsOptions, PgTlsOptions};
+
+#[cfg(feature = "config-file")]
+mod file;
+pub mod mssql;
+pub mod pgsql;
+
+#[cfg(feature = "config-file")]
+pub use file::ManagerConfig;
+pub use pgsql::PgConnectParams;
+
+/// Error for MSSQL pools asking for Windows authentication elsewhere
+pub(crate) const INTEGRATED_SECURITY_UNSUPPORTED: &str =
+  "Integrated security is only supported on Windows, set User ID and Password instead";
+
+/// Database systems a pool can connect to, whether or not their feature is enabled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+  Mssql,
+  Pgsql,
+}
+
+impl Backend {
+  /// Whether the crate was built with this backend's feature
+  pub fn is_enabled(&self) -> bool {
+    match self {
+      Backend::Mssql => cfg!(feature = "mssql"),
+      Backend::Pgsql => cfg!(feature = "pgsql"),
+    }
+  }
+
+  fn feature(&self) -> &'static str {
+    match self {
+      Backend::Mssql => "mssql",
+      Backend::Pgsql => "pgsql",
+    }
+  }
+}
+
+impl fmt::Display for Backend {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Backend::Mssql => write!(f, "MSSQL"),
+      Backend::Pgsql => write!(f, "PostgreSQL"),
+    }
+  }
+}
+
+impl FromStr for Backend {
+  type Err = anyhow::Error;
+
+  fn from_str(s: &str) -> Result<Self> {
+    match s.to_ascii_lowercase().as_str() {
+      "mssql" | "sqlserver" => Ok(Backend::Mssql),
+      "pgsql" | "postgres" | "postgresql" => Ok(Backend::Pgsql),
+      _ => Err(anyhow::anyhow!("Unknown database backend `{}`", s)),
+    }
+  }
+}
+
+/// Typed description of a pool: where to connect, how to authenticate and how many
+/// connections to keep. Build it field by field, or parse an ADO.NET, JDBC or PostgreSQL
+/// connection string with [`PoolConfig::parse`] and adjust the result.
+///
+/// ```ignore
+/// let config = PoolConfig::new(Backend::Pgsql)
+///   .host("db.example.com")
+///   .port(5432)
+///   .database("app")
+///   .credentials("app", "secret")
+///   .max_size(10);
+/// manager.init_pool_with_config("pg_pool", config).await?;
+/// ```
+///
+/// With the `serde` feature it can be deserialized from a table holding either a `url` or a
+/// `backend`, plus any of the fields below (`command_timeout`, `log_parameters` and
+/// `retry_policy` stand for [`PoolOptions`]). Durations are seconds or strings like `"500ms"`.
+#[derive(Clone)]
+#[cfg_attr(
+  feature = "serde",
+  derive(serde::Deserialize),
+  serde(try_from = "RawPoolConfig")
+)]
+pub struct PoolConfig {
+  pub backend: Backend,
+  /// Hosts to connect to, with an optional port each, tried in order until one accepts the
+  /// connection. With several MSSQL hosts, read-write pools also skip hosts whose database is
+  /// read-only; PostgreSQL does so with the `target_session_attrs=read-write` parameter.
+  pub hosts: Vec<(String, Option<u16>)>,
+  /// Port for hosts without their own, the backend's default if unset.
+  pub port: Option<u16>,
+  /// Named MSSQL instance, resolved to its port through the SQL Server Browser unless the host
+  /// has an explicit port.
+  pub instance_name: Option<String>,
+  /// UDP port of the SQL Server Browser, 1434 if unset.
+  pub sql_browser_port: Option<u16>,
+  pub database: Option<String>,
+  pub user: Option<String>,
+  pub password: Option<String>,
+  /// Asked for the user and password of every new connection, taking precedence over the
+  /// fields above.
+  pub credentials: Option<Arc<dyn CredentialProvider>>,
+  /// Windows authentication for MSSQL instead of a SQL login. Only supported on Windows,
+  /// elsewhere creating the pool fails.
+  pub integrated_security: bool,
+  pub application_name: Option<String>,
+  /// Open read-only connections (`ApplicationIntent=ReadOnly`, MSSQL only).
+  pub read_only: bool,
+  /// Connect to every address the MSSQL host name resolves to in parallel, for availability
+  /// group listeners spanning several subnets (`MultiSubnetFailover=True`).
+  pub multi_subnet_failover: bool,
+  /// Most connections the pool opens.
+  pub max_size: u32,
+  /// Connections opened up front, `max_size` if unset. The rest are opened on demand.
+  pub min_size: Option<u32>,
+  pub connect_timeout: Option<Duration>,
+  pub mssql_tls: MssqlTlsOptions,
+  pub pg_tls: PgTlsOptions,
+  /// Other libpq parameters (`options`, `keepalives`, `target_session_attrs`, ...), passed to
+  /// the PostgreSQL driver as-is.
+  pub params: Vec<(String, String)>,
+  pub options: PoolOptions,
+  pub hooks: PoolHooks,
+}
+
+impl PoolConfig {
+  /// Empty configuration for `backend`, with a pool size of 10
+  pub fn new(backend: Backend) -> Self {
+    Self {
+      backend,
+      hosts: Vec::new(),
+      port: None,
+      instance_name: None,
+      sql_browser_port: None,
+      database: None,
+      user: None,
+      password: None,
+      credentials: None,
+      integrated_security: false,
+      application_name: None,
+      read_only: false,
+      multi_subnet_failover: false,
+      max_size: 10,
+      min_size: None,
+      connect_timeout: None,
+      mssql_tls: MssqlTlsOptions::default(),
+      pg_tls: PgTlsOptions::default(),
+      params: Vec::new(),
+      options: PoolOptions::default(),
+      hooks: PoolHooks::default(),
+    }
+  }
+
+  /// Parse an ADO.NET (`Server=...;`), JDBC (`jdbc:sqlserver://...`), PostgreSQL URL
+  /// (`postgresql://...`) or libpq `key=value` connection string
+  pub fn parse(conn_str: &str) -> Result<Self> {
+    let conn_str = conn_str.trim();
+    if conn_str.starts_with("jdbc:") {
+      mssql::parse_jdbc(conn_str)
+    } else if PgConnectParams::is_pgsql(conn_str) {
+      pgsql::parse(&PgConnectParams::parse(conn_str)?)
+    } else if conn_str.contains('=') {
+      mssql::parse_ado(conn_str)
+    } else {
+      Err(anyhow::anyhow!("Unrecognized connection string"))
+    }
+  }
+
+  /// Connect to a single host, replacing any host set before
+  pub fn host(mut self, host: impl Into<String>) -> Self {
+    self.hosts = vec![(host.into(), None)];
+    self
+  }
+
+  /// Add a host tried after the ones set before, see [`PoolConfig::hosts`]
+  pub fn add_host(mut self, host: impl Into<String>, port: Option<u16>) -> Self {
+    self.hosts.push((host.into(), port));
+    self
+  }
+
+  pub fn port(mut self, port: u16) -> Self {
+    self.port = Some(port);
+    self
+  }
+
+  pub fn instance_name(mut self, instance_name: impl Into<String>) -> Self {
+    self.instance_name = Some(instance_name.into());
+    self
+  }
+
+  pub fn sql_browser_port(mut self, port: u16) -> Self {
+    self.sql_browser_port = Some(port);
+    self
+  }
+
+  pub fn database(mut self, database: impl Into<String>) -> Self {
+    self.database = Some(database.into());
+    self
+  }
+
+  pub fn credentials(mut self, user: impl Into<String>, password: impl Into<String>) -> Self {
+    self.user = Some(user.into());
+    self.password = Some(password.into());
+    self
+  }
+
+  pub fn credential_provider(mut self, provider: impl CredentialProvider + 'static) -> Self {
+    self.credentials = Some(Arc::new(provider));
+    self
+  }
+
+  pub fn integrated_security(mut self, integrated_security: bool) -> Self {
+    self.integrated_security = integrated_security;
+    self
+  }
+
+  pub fn application_name(mut self, application_name: impl Into<String>) -> Self {
+    self.application_name = Some(application_name.into());
+    self
+  }
+
+  pub fn read_only(mut self, read_only: bool) -> Self {
+    self.read_only = read_only;
+    self
+  }
+
+  pub fn multi_subnet_failover(mut self, multi_subnet_failover: bool) -> Self {
+    self.multi_subnet_failover = multi_subnet_failover;
+    self
+  }
+
+  pub fn max_size(mut self, max_size: u32) -> Self {
+    self.max_size = max_size;
+    self
+  }
+
+  pub fn min_size(mut self, min_size: u32) -> Self {
+    self.min_size = Some(min_size);
+    self
+  }
+
+  pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+    self.connect_timeout = Some(timeout);
+    self
+  }
+
+  pub fn command_timeout(mut self, timeout: Duration) -> Self {
+    self.options.command_timeout = Some(timeout);
+    self
+  }
+
+  pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+    self.options.retry_policy = retry_policy;
+    self
+  }
+
+  pub fn mssql_tls(mut self, mssql_tls: MssqlTlsOptions) -> Self {
+    self.mssql_tls = mssql_tls;
+    self
+  }
+
+  pub fn pg_tls(mut self, pg_tls: PgTlsOptions) -> Self {
+    self.pg_tls = pg_tls;
+    self
+  }
+
+  /// Set a backend-specific parameter, see [`PoolConfig::params`]
+  pub fn param(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+    self.params.push((key.into(), value.into()));
+    self
+  }
+
+  pub fn options(mut self, options: PoolOptions) -> Self {
+    self.options = options;
+    self
+  }
+
+  /// Run `hook` on every new connection, see [`PoolHooks::after_connect`]
+  ///
+  /// ```ignore
+  /// config.after_connect(|client| Box::pin(async move {
+  ///   client.batch_execute("SET TIME ZONE 'UTC'; SET search_path TO app").await
+  /// }))
+  /// ```
+  pub fn after_connect<F>(mut self, hook: F) -> Self
+  where
+    F: for<'a> Fn(&'a mut DbClient) -> HookFuture<'a> + Send + Sync + 'static,
+  {
+    self.hooks.after_connect = Some(Arc::new(hook));
+    self
+  }
+
+  /// Run `hook` on an idle connection before handing it out, see [`PoolHooks::before_acquire`]
+  pub fn before_acquire<F>(mut self, hook: F) -> Self
+  where
+    F: for<'a> Fn(&'a mut DbClient) -> HookFuture<'a> + Send + Sync + 'static,
+  {
+    self.hooks.before_acquire = Some(Arc::new(hook));
+    self
+  }
+
+  /// Run `hook` on every connection coming back to the pool, see [`PoolHooks::after_release`]
+  pub fn after_release<F>(mut self, hook: F) -> Self
+  where
+    F: for<'a> Fn(&'a mut DbClient) -> HookFuture<'a> + Send + Sync + 'static,
+  {
+    self.hooks.after_release = Some(Arc::new(hook));
+    self
+  }
+
+  /// This configuration with the user and password of the credential provider, if any
+  pub(crate) async fn resolve_credentials(&self) -> Result<Cow<'_, PoolConfig>> {
+    let Some(provider) = &self.credentials else {
+      return Ok(Cow::Borrowed(self));
+    };
+    let credentials = provider.credentials().await?;
+    let mut config = self.clone();
+    config.user = credentials.user.or(config.user);
+    config.password = credentials.password.or(config.password);
+    Ok(Cow::Owned(config))
+  }
+
+  /// Connections to open when the pool is created
+  pub(crate) fn initial_size(&self) -> u32 {
+    self.min_size.unwrap_or(self.max_size)
+  }
+
+  /// Check the configuration can be used with this build
+  pub(crate) fn validate(&self) -> Result<()> {
+    if !self.backend.is_enabled() {
+      return Err(anyhow::anyhow!(
+        "{} support is not enabled, build with the `{}` feature",
+        self.backend,
+        self.backend.feature()
+      ));
+    }
+    if self.backend == Backend::Mssql && self.integrated_security && cfg!(not(windows)) {
+      return Err(anyhow::anyhow!("{}", INTEGRATED_SECURITY_UNSUPPORTED));
+    }
+    if self.max_size == 0 {
+      return Err(anyhow::anyhow!("Pool size must be at least 1"));
+    }
+    if self.initial_size() > self.max_size {
+      return Err(anyhow::anyhow!(
+        "Minimum pool size {} is larger than the maximum {}",
+        self.initial_size(),
+        self.max_size
+      ));
+    }
+    Ok(())
+  }
+}
+
+impl fmt::Debug for PoolConfig {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("PoolConfig")
+      .field("backend", &self.backend)
+      .field("hosts", &self.hosts)
+      .field("port", &self.port)
+      .field("instance_name", &self.instance_name)
+      .field("sql_browser_port", &self.sql_browser_port)
+      .field("database", &self.database)
+      .field("user", &self.user)
+      .field("password", &self.password.as_ref().map(|_| "<redacted>"))
+      .field(
+        "credentials",
+        &self.credentials.as_ref().map(|_| "<provider>"),
+      )
+      .field("integrated_security", &self.integrated_security)
+      .field("application_name", &self.application_name)
+      .field("read_only", &self.read_only)
+      .field("multi_subnet_failover", &self.multi_subnet_failover)
+      .field("max_size", &self.max_size)
+      .field("min_size", &self.min_size)
+      .field("connect_timeout", &self.connect_timeout)
+      .field("mssql_tls", &self.mssql_tls)
+      .field("pg_tls", &self.pg_tls)
+      .field("params", &self.params)
+      .field("options", &self.options)
+      .field("hooks", &self.hooks)
+      .finish()
+  }
+}
+
+/// Parse a boolean connection string value
+fn parse_bool(key: &str, value: &str) -> Result<bool> {
+  match value.trim().to_ascii_lowercase().as_str() {
+    "true" | "yes" | "1" => Ok(true),
+    "false" | "no" | "0" => Ok(false),
+    _ => Err(anyhow::anyhow!("Invalid boolean `{}` for `{}`", value, key)),
+  }
+}
+
+/// Parse a timeout given in whole seconds, where zero means no timeout
+fn parse_seconds(key: &str, value: &str) -> Result<Option<Duration>> {
+  let secs: i64 = value
+    .trim()
+    .parse()
+    .map_err(|_| anyhow::anyhow!("Invalid number of seconds `{}` for `{}`", value, key))?;
+  Ok((secs > 0).then(|| Duration::from_secs(secs as u64)))
+}
+
+/// Serialized form of a [`PoolConfig`], overlaid on the parsed `url` if there is one.
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawPoolConfig {
+  url: Option<String>,
+  backend: Option<String>,
+  host: Option<String>,
+  /// `host[:port]` entries, tried in order
+  hosts: Option<Vec<String>>,
+  port: Option<u16>,
+  instance_name: Option<String>,
+  sql_browser_port: Option<u16>,
+  database: Option<String>,
+  user: Option<String>,
+  password: Option<String>,
+  credentials: Option<CredentialSource>,
+  integrated_security: Option<bool>,
+  application_name: Option<String>,
+  read_only: Option<bool>,
+  multi_subnet_failover: Option<bool>,
+  max_size: Option<u32>,
+  min_size: Option<u32>,
+  #[serde(default, deserialize_with = "deserialize_opt_duration")]
+  connect_timeout: Option<Duration>,
+  #[serde(default, deserialize_with = "deserialize_opt_duration")]
+  command_timeout: Option<Duration>,
+  log_parameters: Option<bool>,
+  retry_policy: Option<RetryPolicy>,
+  mssql_tls: Option<MssqlTlsOptions>,
+  pg_tls: Option<PgTlsOptions>,
+  #[serde(default)]
+  params: std::collections::BTreeMap<String, String>,
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<RawPoolConfig> for PoolConfig {
+  type Error = anyhow::Error;
+
+  fn try_from(raw: RawPoolConfig) -> Result<Self> {
+    let backend = raw.backend.as_deref().map(Backend::from_str).transpose()?;
+    let mut config = match (&raw.url, backend) {
+      (Some(url), backend) => {
+        let config = PoolConfig::parse(url)?;
+        if backend.is_some_and(|backend| backend != config.backend) {
+          return Err(anyhow::anyhow!(
+            "`backend` does not match the {} connection string",
+            config.backend
+          ));
+        }
+        config
+      }
+      (None, Some(backend)) => PoolConfig::new(backend),
+      (None, None) => return Err(anyhow::anyhow!("Either `url` or `backend` is required")),
+    };
+
+    match (raw.host, raw.hosts) {
+      (Some(_), Some(_)) => return Err(anyhow::anyhow!("Use either `host` or `hosts`")),
+      (Some(host), None) => config.hosts = vec![(host, None)],
+      (None, Some(hosts)) => {
+        config.hosts = hosts
+          .iter()
+          .map(|spec| {
+            let (host, port) = pgsql::split_host_port(spec)?;
+            let port = (!port.is_empty()).then(|| port.parse()).transpose()?;
+            Ok((host.to_string(), port))
+          })
+          .collect::<Result<_>>()?;
+      }
+      (None, None) => {}
+    }
+    config.port = raw.port.or(config.port);
+    config.instance_name = raw.instance_name.or(config.instance_name);
+    config.sql_browser_port = raw.sql_browser_port.or(config.sql_browser_port);
+    config.database = raw.database.or(config.database);
+    config.user = raw.user.or(config.user);
+    config.password = raw.password.or(config.password);
+    if let Some(source) = raw.credentials {
+      config.credentials = Some(source.provider()?);
+    }
+    config.integrated_security = raw
+      .integrated_security
+      .unwrap_or(config.integrated_security);
+    config.application_name = raw.application_name.or(config.application_name);
+    config.read_only = raw.read_only.unwrap_or(config.read_only);
+    config.multi_subnet_failover = raw
+      .multi_subnet_failover
+      .unwrap_or(config.multi_subnet_failover);
+    config.max_size = raw.max_size.unwrap_or(config.max_size);
+    config.min_size = raw.min_size.or(config.min_size);
+    config.connect_timeout = raw.connect_timeout.or(config.connect_timeout);
+    config.options.command_timeout = raw.command_timeout.or(config.options.command_timeout);
+    config.options.log_parameters = raw.log_parameters.unwrap_or(config.options.log_parameters);
+    if let Some(retry_policy) = raw.retry_policy {
+      config.options.retry_policy = retry_policy;
+    }
+    if let Some(mssql_tls) = raw.mssql_tls {
+      config.mssql_tls = mssql_tls;
+    }
+    if let Some(pg_tls) = raw.pg_tls {
+      config.pg_tls = pg_tls;
+    }
+    config.params.extend(raw.params);
+    Ok(config)
+  }
+}
+
+/// Built-in credential provider named in a config file: `credentials = { file = "/run/secrets/db" }`,
+/// `{ env = "DB_PASSWORD" }` or `{ command = ["vault", "read", "-field=password", "db/app"] }`.
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+enum CredentialSource {
+  File(std::path::PathBuf),
+  Env(String),
+  Command(Vec<String>),
+}
+
+#[cfg(feature = "serde")]
+impl CredentialSource {
+  fn provider(self) -> Result<Arc<dyn CredentialProvider>> {
+    use crate::credentials::{CommandCredentials, EnvCredentials, FileCredentials};
+
+    Ok(match self {
+      CredentialSource::File(path) => Arc::new(FileCredentials::new(path)),
+      CredentialSource::Env(var) => Arc::new(EnvCredentials::new(var)),
+      CredentialSource::Command(command) => {
+        let (program, args) = command
+          .split_first()
+          .ok_or_else(|| anyhow::anyhow!("Credential `command` must not be empty"))?;
+        let provider = args
+          .iter()
+          .fold(CommandCredentials::new(program), |provider, arg| {
+            provider.arg(arg)
+          });
+        Arc::new(provider)
+      }
+    })
+  }
+}
+
+/// Duration given as seconds (`30`, `0.5`) or with a unit (`"500ms"`, `"30s"`, `"5m"`, `"1h"`)
+#[cfg(feature = "serde")]
+pub(crate) fn deserialize_duration<'de, D>(
+  deserializer: D,
+) -> std::result::Result<Duration, D::Error>
+where
+  D: serde::Deserializer<'de>,
+{
+  #[derive(serde::Deserialize)]
+  #[serde(untagged)]
+  enum Value {
+    Seconds(f64),
+    Text(String),
+  }
+
+  let secs = match <Value as serde::Deserialize>::deserialize(deserializer)? {
+    Value::Seconds(secs) => secs,
+    Value::Text(text) => {
+      let text = text.trim();
+      let split = text
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(text.len());
+      let (number, unit) = text.split_at(split);
+      let number: f64 = number
+        .parse()
+        .map_err(|_| serde::de::Error::custom(format!("invalid duration `{}`", text)))?;
+      match unit.trim() {
+        "ms" => number / 1000.0,
+        "" | "s" => number,
+        "m" => number * 60.0,
+        "h" => number * 3600.0,
+        unit => {
+          return Err(serde::de::Error::custom(format!(
+            "invalid duration unit `{}`",
+            unit
+          )));
+        }
+      }
+    }
+  };
+  Duration::try_from_secs_f64(secs).map_err(serde::de::Error::custom)
+}
+
+#[cfg(feature = "serde")]
+pub(crate) fn deserialize_opt_duration<'de, D>(
+  deserializer: D,
+) -> std::result::Result<Option<Duration>, D::Error>
+where
+  D: serde::Deserializer<'de>,
+{
+  deserialize_duration(deserializer).map(Some)
+}
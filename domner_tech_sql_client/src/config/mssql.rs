@@ -0,0 +1,376 @@
+use anyhow::Result;
+use connection_string::{AdoNetString, JdbcString};
+
+use super::{Backend, PoolConfig, parse_bool, parse_seconds};
+
+#[cfg(feature = "mssql")]
+use crate::types::sql::mssql::{AuthMethod, Config};
+
+/// Parse an ADO.NET connection string (`Server=tcp:host,1433;Database=...;User Id=...`)
+pub(crate) fn parse_ado(conn_str: &str) -> Result<PoolConfig> {
+  let pairs: AdoNetString = conn_str
+    .parse()
+    .map_err(|e| anyhow::anyhow!("Invalid ADO.NET connection string: {}", e))?;
+  let mut config = PoolConfig::new(Backend::Mssql);
+  let has_integrated_security = pairs
+    .keys()
+    .any(|key| normalize(key) == "integratedsecurity");
+  let mut failover_partner = None;
+  for (key, value) in pairs.iter() {
+    match normalize(key).as_str() {
+      // `Integrated Security` wins over its older `Trusted_Connection` alias
+      "trustedconnection" if has_integrated_security => {}
+      // Applied once the server is known, whatever the key order
+      "failoverpartner" => failover_partner = Some(value),
+      _ => set(&mut config, key, value)?,
+    }
+  }
+  if let Some(partner) = failover_partner {
+    add_failover_partner(&mut config, partner)?;
+  }
+  Ok(config)
+}
+
+/// Parse a JDBC connection string (`jdbc:sqlserver://host\instance:1433;databaseName=...`)
+pub(crate) fn parse_jdbc(conn_str: &str) -> Result<PoolConfig> {
+  let jdbc: JdbcString = conn_str
+    .parse()
+    .map_err(|e| anyhow::anyhow!("Invalid JDBC connection string: {}", e))?;
+  if jdbc.sub_protocol() != "jdbc:sqlserver" {
+    return Err(anyhow::anyhow!(
+      "Unsupported JDBC sub-protocol `{}`",
+      jdbc.sub_protocol()
+    ));
+  }
+  let mut config = PoolConfig::new(Backend::Mssql);
+  if let Some(host) = jdbc.server_name() {
+    config.hosts = vec![(host.to_string(), None)];
+  }
+  config.instance_name = jdbc.instance_name().map(str::to_string);
+  config.port = jdbc.port();
+  let mut failover_partner = None;
+  for (key, value) in jdbc.properties() {
+    match normalize(key).as_str() {
+      "failoverpartner" => failover_partner = Some(value),
+      _ => set(&mut config, key, value)?,
+    }
+  }
+  if let Some(partner) = failover_partner {
+    add_failover_partner(&mut config, partner)?;
+  }
+  Ok(config)
+}
+
+/// Property name without case, spaces or underscores
+fn normalize(key: &str) -> String {
+  key
+    .chars()
+    .filter(|c| *c != ' ' && *c != '_')
+    .collect::<String>()
+    .to_ascii_lowercase()
+}
+
+/// Apply one ADO.NET or JDBC property
+fn set(config: &mut PoolConfig, key: &str, value: &str) -> Result<()> {
+  let value = value.trim();
+  match normalize(key).as_str() {
+    "server" | "datasource" | "address" | "addr" | "networkaddress" => set_server(config, value)?,
+    "servername" => config.hosts = vec![(value.to_string(), None)],
+    "instancename" => config.instance_name = Some(value.to_string()),
+    "port" | "portnumber" => {
+      config.port = Some(
+        value
+          .parse()
+          .map_err(|_| anyhow::anyhow!("Invalid port `{}`", value))?,
+      )
+    }
+    "database" | "initialcatalog" | "databasename" => config.database = Some(value.to_string()),
+    "uid" | "user" | "userid" | "username" => config.user = Some(value.to_string()),
+    "password" | "pwd" => config.password = Some(value.to_string()),
+    "integratedsecurity" | "trustedconnection" => {
+      config.integrated_security = value.eq_ignore_ascii_case("sspi") || parse_bool(key, value)?
+    }
+    "applicationname" | "app" => config.application_name = Some(value.to_string()),
+    "applicationintent" => config.read_only = value.eq_ignore_ascii_case("readonly"),
+    "encrypt" if value.eq_ignore_ascii_case("danger_plaintext") => {
+      config.mssql_tls.danger_plaintext = true
+    }
+    // `Strict` (TDS 8) is not supported by the driver; the closest is required encryption
+    "encrypt"
+      if value.eq_ignore_ascii_case("strict") || value.eq_ignore_ascii_case("mandatory") =>
+    {
+      config.mssql_tls.require_encryption = true
+    }
+    "encrypt" if value.eq_ignore_ascii_case("optional") => {
+      config.mssql_tls.require_encryption = false
+    }
+    "encrypt" => config.mssql_tls.require_encryption = parse_bool(key, value)?,
+    "trustservercertificate" => config.mssql_tls.trust_server_certificate = parse_bool(key, value)?,
+    "trustservercertificateca" => config.mssql_tls.ca_cert = Some(value.into()),
+    "multisubnetfailover" => config.multi_subnet_failover = parse_bool(key, value)?,
+    "connecttimeout" | "connectiontimeout" | "timeout" | "logintimeout" => {
+      config.connect_timeout = parse_seconds(key, value)?
+    }
+    "maxpoolsize" => {
+      config.max_size = value
+        .parse()
+        .map_err(|_| anyhow::anyhow!("Invalid pool size `{}`", value))?
+    }
+    "minpoolsize" => {
+      config.min_size = Some(
+        value
+          .parse()
+          .map_err(|_| anyhow::anyhow!("Invalid pool size `{}`", value))?,
+      )
+    }
+    // Like other ADO.NET parsers, options this driver has no use for (`Workstation ID`,
+    // `Packet Size`, `Connect Retry Count`, ...) are ignored
+    _ => {
+      #[cfg(feature = "tracing")]
+      tracing::debug!(option = key, "ignoring unsupported MSSQL connection option");
+    }
+  }
+  Ok(())
+}
+
+/// `[tcp:]host[\instance][,port]`, with `(local)` and `.` meaning this machine
+fn set_server(config: &mut PoolConfig, value: &str) -> Result<()> {
+  let (host, instance, port) = split_server(value)?;
+  config.hosts = vec![(host, None)];
+  config.instance_name = instance;
+  if port.is_some() {
+    config.port = port;
+  }
+  Ok(())
+}
+
+/// Add the `Failover Partner` host, tried when the server is down or not writable. Without a
+/// port of its own it uses the server's.
+fn add_failover_partner(config: &mut PoolConfig, value: &str) -> Result<()> {
+  let (host, instance, port) = split_server(value)?;
+  if instance.is_some() && instance != config.instance_name {
+    return Err(anyhow::anyhow!(
+      "Failover partner `{}` must use the same instance as the server",
+      value
+    ));
+  }
+  config.hosts.push((host, port));
+  Ok(())
+}
+
+fn split_server(value: &str) -> Result<(String, Option<String>, Option<u16>)> {
+  let value = value.strip_prefix("tcp:").unwrap_or(value);
+  let (server, port) = match value.split_once(',') {
+    Some((server, port)) => (server, Some(port.trim())),
+    None => (value, None),
+  };
+  let (host, instance) = match server.split_once('\\') {
+    Some((host, instance)) => (host, Some(instance.to_string())),
+    None => (server, None),
+  };
+  let host = match host.trim() {
+    "(local)" | "." | "" => "localhost",
+    host => host,
+  };
+  let port = port
+    .map(|port| {
+      port
+        .parse()
+        .map_err(|_| anyhow::anyhow!("Invalid port `{}`", port))
+    })
+    .transpose()?;
+  Ok((host.to_string(), instance, port))
+}
+
+/// Build the tiberius configuration of an MSSQL pool, one per host in the order they are tried,
+/// each with whether its port has to be looked up through the SQL Server Browser
+#[cfg(feature = "mssql")]
+pub(crate) fn driver_configs(config: &PoolConfig) -> Result<Vec<(Config, bool)>> {
+  if config.hosts.is_empty() {
+    return Ok(vec![driver_config(config, None)?]);
+  }
+  config
+    .hosts
+    .iter()
+    .map(|host| driver_config(config, Some(host)))
+    .collect()
+}
+
+#[cfg(feature = "mssql")]
+fn driver_config(
+  config: &PoolConfig,
+  host: Option<&(String, Option<u16>)>,
+) -> Result<(Config, bool)> {
+  let mut driver = Config::new();
+  if let Some((host, _)) = host {
+    driver.host(host);
+  }
+  let port = host.and_then(|(_, port)| *port).or(config.port);
+  if let Some(instance_name) = &config.instance_name {
+    driver.instance_name(instance_name);
+  }
+  // A named instance without a port is found by asking the browser, which tiberius reaches
+  // through the configured port
+  let browse = port.is_none() && config.instance_name.is_some();
+  if let Some(port) = port.or(config.sql_browser_port.filter(|_| browse)) {
+    driver.port(port);
+  }
+  if let Some(database) = &config.database {
+    driver.database(database);
+  }
+  if let Some(application_name) = &config.application_name {
+    driver.application_name(application_name);
+  }
+  driver.readonly(config.read_only);
+  if config.integrated_security {
+    #[cfg(windows)]
+    driver.authentication(AuthMethod::Integrated);
+    // Rejected by `PoolConfig::validate` already, never fall back to an empty SQL login
+    #[cfg(not(windows))]
+    return Err(anyhow::anyhow!(
+      "{}",
+      super::INTEGRATED_SECURITY_UNSUPPORTED
+    ));
+  } else {
+    driver.authentication(AuthMethod::sql_server(
+      config.user.as_deref().unwrap_or(""),
+      config.password.as_deref().unwrap_or(""),
+    ));
+  }
+  config.mssql_tls.apply(&mut driver)?;
+  Ok((driver, browse))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn ado_string() {
+    let config = parse_ado(
+      "Server=tcp:db.local,1444;Initial Catalog=orders;User Id=app;Password=secret;\
+       Application Name=api;ApplicationIntent=ReadOnly;Connect Timeout=5;Max Pool Size=20",
+    )
+    .unwrap();
+    assert_eq!(config.hosts, vec![("db.local".to_string(), None)]);
+    assert_eq!(config.port, Some(1444));
+    assert_eq!(config.database.as_deref(), Some("orders"));
+    assert_eq!(config.user.as_deref(), Some("app"));
+    assert_eq!(config.password.as_deref(), Some("secret"));
+    assert_eq!(config.application_name.as_deref(), Some("api"));
+    assert!(config.read_only);
+    assert_eq!(
+      config.connect_timeout,
+      Some(std::time::Duration::from_secs(5))
+    );
+    assert_eq!(config.max_size, 20);
+  }
+
+  #[test]
+  fn quoted_values_may_contain_semicolons() {
+    for conn_str in [
+      "Server=db.local;Password=\"a;b=c\";Database=orders",
+      "Server=db.local;Password='a;b=c';Database=orders",
+      "Server=db.local;Password={a;b=c};Database=orders",
+    ] {
+      let config = parse_ado(conn_str).unwrap();
+      assert_eq!(config.password.as_deref(), Some("a;b=c"), "{}", conn_str);
+      assert_eq!(config.database.as_deref(), Some("orders"), "{}", conn_str);
+    }
+  }
+
+  #[test]
+  fn named_instance_and_local_server() {
+    let config = parse_ado("Data Source=(local)\\SQLEXPRESS;Integrated Security=SSPI").unwrap();
+    assert_eq!(config.hosts, vec![("localhost".to_string(), None)]);
+    assert_eq!(config.instance_name.as_deref(), Some("SQLEXPRESS"));
+    assert!(config.integrated_security);
+  }
+
+  #[test]
+  fn unknown_options_are_ignored() {
+    let config = parse_ado(
+      "Server=localhost;Workstation ID=box;Packet Size=8192;Connect Retry Count=3;\
+       Persist Security Info=False;Database=orders",
+    )
+    .unwrap();
+    assert_eq!(config.database.as_deref(), Some("orders"));
+  }
+
+  #[test]
+  fn invalid_values_of_known_options_fail() {
+    assert!(parse_ado("Server=localhost,abc").is_err());
+    assert!(parse_ado("Server=localhost;Encrypt=sometimes").is_err());
+    assert!(parse_ado("Server=localhost;Max Pool Size=-1").is_err());
+  }
+
+  #[test]
+  fn encrypt_values() {
+    let encrypt = |value: &str| {
+      parse_ado(&format!("Server=localhost;Encrypt={}", value))
+        .unwrap()
+        .mssql_tls
+    };
+    assert!(encrypt("Strict").require_encryption);
+    assert!(encrypt("Mandatory").require_encryption);
+    assert!(encrypt("yes").require_encryption);
+    assert!(!encrypt("Optional").require_encryption);
+    assert!(!encrypt("false").require_encryption);
+    assert!(encrypt("DANGER_PLAINTEXT").danger_plaintext);
+  }
+
+  #[test]
+  fn integrated_security_wins_over_trusted_connection() {
+    let config =
+      parse_ado("Server=localhost;Trusted_Connection=Yes;Integrated Security=false").unwrap();
+    assert!(!config.integrated_security);
+  }
+
+  #[cfg(all(feature = "mssql", not(windows)))]
+  #[test]
+  fn integrated_security_fails_off_windows() {
+    let config = parse_ado("Server=localhost;Integrated Security=true").unwrap();
+    let err = config.validate().err().unwrap();
+    assert!(
+      err.to_string().contains("only supported on Windows"),
+      "{}",
+      err
+    );
+    #[cfg(feature = "mssql")]
+    assert!(driver_configs(&config).is_err());
+  }
+
+  #[test]
+  fn failover_partner_is_applied_after_the_server() {
+    let config = parse_ado("Failover Partner=db2;Server=db1,1444").unwrap();
+    assert_eq!(
+      config.hosts,
+      vec![("db1".to_string(), None), ("db2".to_string(), None)]
+    );
+    assert_eq!(config.port, Some(1444));
+  }
+
+  #[test]
+  fn jdbc_string() {
+    let config = parse_jdbc(
+      "jdbc:sqlserver://db.local\\SQLEXPRESS:1444;databaseName=orders;user=app;\
+       password={a;b=c};encrypt=true;someUnknownProperty=1",
+    )
+    .unwrap();
+    assert_eq!(config.hosts, vec![("db.local".to_string(), None)]);
+    assert_eq!(config.instance_name.as_deref(), Some("SQLEXPRESS"));
+    assert_eq!(config.port, Some(1444));
+    assert_eq!(config.database.as_deref(), Some("orders"));
+    assert_eq!(config.user.as_deref(), Some("app"));
+    assert_eq!(config.password.as_deref(), Some("a;b=c"));
+    assert!(config.mssql_tls.require_encryption);
+  }
+
+  #[test]
+  fn jdbc_properties_without_server() {
+    let config = parse_jdbc("jdbc:sqlserver://;serverName=db.local;portNumber=1444").unwrap();
+    assert_eq!(config.hosts, vec![("db.local".to_string(), None)]);
+    assert_eq!(config.port, Some(1444));
+    assert!(parse_jdbc("jdbc:mysql://db.local").is_err());
+  }
+}
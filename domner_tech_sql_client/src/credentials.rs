@@ -0,0 +1,185 @@
+use anyhow::Result;
+use std::{fmt, future::Future, path::PathBuf, pin::Pin, time::Duration};
+
+/// Future returned by [`CredentialProvider::credentials`].
+pub type CredentialFuture<'a> = Pin<Box<dyn Future<Output = Result<Credentials>> + Send + 'a>>;
+
+/// User name and password for one connection. `None` keeps the value from the pool config.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Credentials {
+  pub user: Option<String>,
+  pub password: Option<String>,
+}
+
+impl Credentials {
+  pub fn password(password: impl Into<String>) -> Self {
+    Self {
+      user: None,
+      password: Some(password.into()),
+    }
+  }
+}
+
+impl fmt::Debug for Credentials {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("Credentials")
+      .field("user", &self.user)
+      .field("password", &self.password.as_ref().map(|_| "<redacted>"))
+      .finish()
+  }
+}
+
+/// Source of connection credentials, asked every time a pool opens a connection so rotated
+/// secrets are used without restarting.
+pub trait CredentialProvider: Send + Sync {
+  fn credentials(&self) -> CredentialFuture<'_>;
+}
+
+/// Reads the password (and optionally the user) from files, such as mounted secrets.
+#[derive(Debug, Clone)]
+pub struct FileCredentials {
+  pub password_path: PathBuf,
+  pub user_path: Option<PathBuf>,
+}
+
+impl FileCredentials {
+  pub fn new(password_path: impl Into<PathBuf>) -> Self {
+    Self {
+      password_path: password_path.into(),
+      user_path: None,
+    }
+  }
+
+  pub fn user_path(mut self, user_path: impl Into<PathBuf>) -> Self {
+    self.user_path = Some(user_path.into());
+    self
+  }
+}
+
+impl CredentialProvider for FileCredentials {
+  fn credentials(&self) -> CredentialFuture<'_> {
+    Box::pin(async move {
+      let user = match &self.user_path {
+        Some(path) => Some(read_secret_file(path).await?),
+        None => None,
+      };
+      Ok(Credentials {
+        user,
+        password: Some(read_secret_file(&self.password_path).await?),
+      })
+    })
+  }
+}
+
+async fn read_secret_file(path: &PathBuf) -> Result<String> {
+  let contents = tokio::fs::read_to_string(path)
+    .await
+    .map_err(|e| anyhow::anyhow!("Failed to read secret {}: {}", path.display(), e))?;
+  Ok(contents.trim_end_matches(['\r', '\n']).to_string())
+}
+
+/// Reads the password (and optionally the user) from environment variables.
+#[derive(Debug, Clone)]
+pub struct EnvCredentials {
+  pub password_var: String,
+  pub user_var: Option<String>,
+}
+
+impl EnvCredentials {
+  pub fn new(password_var: impl Into<String>) -> Self {
+    Self {
+      password_var: password_var.into(),
+      user_var: None,
+    }
+  }
+
+  pub fn user_var(mut self, user_var: impl Into<String>) -> Self {
+    self.user_var = Some(user_var.into());
+    self
+  }
+}
+
+impl CredentialProvider for EnvCredentials {
+  fn credentials(&self) -> CredentialFuture<'_> {
+    Box::pin(async move {
+      let read = |var: &str| {
+        std::env::var(var)
+          .map_err(|e| anyhow::anyhow!("Failed to read environment variable `{}`: {}", var, e))
+      };
+      Ok(Credentials {
+        user: self.user_var.as_deref().map(read).transpose()?,
+        password: Some(read(&self.password_var)?),
+      })
+    })
+  }
+}
+
+/// Runs an external command (a vault CLI, a cloud IAM token generator, ...) and uses its
+/// standard output, without the trailing newline, as the password.
+#[derive(Debug, Clone)]
+pub struct CommandCredentials {
+  pub program: String,
+  pub args: Vec<String>,
+  /// How long the command may run, 30 seconds by default.
+  pub timeout: Duration,
+}
+
+impl CommandCredentials {
+  pub fn new(program: impl Into<String>) -> Self {
+    Self {
+      program: program.into(),
+      args: Vec::new(),
+      timeout: Duration::from_secs(30),
+    }
+  }
+
+  pub fn arg(mut self, arg: impl Into<String>) -> Self {
+    self.args.push(arg.into());
+    self
+  }
+
+  pub fn timeout(mut self, timeout: Duration) -> Self {
+    self.timeout = timeout;
+    self
+  }
+}
+
+impl CredentialProvider for CommandCredentials {
+  fn credentials(&self) -> CredentialFuture<'_> {
+    Box::pin(async move {
+      let output = tokio::process::Command::new(&self.program)
+        .args(&self.args)
+        .kill_on_drop(true)
+        .output();
+      let output = tokio::time::timeout(self.timeout, output)
+        .await
+        .map_err(|_| {
+          anyhow::anyhow!(
+            "Credential command `{}` timed out after {:?}",
+            self.program,
+            self.timeout
+          )
+        })?
+        .map_err(|e| {
+          anyhow::anyhow!("Failed to run credential command `{}`: {}", self.program, e)
+        })?;
+      if !output.status.success() {
+        return Err(anyhow::anyhow!(
+          "Credential command `{}` failed with {}: {}",
+          self.program,
+          output.status,
+          String::from_utf8_lossy(&output.stderr).trim()
+        ));
+      }
+      let password = String::from_utf8(output.stdout).map_err(|_| {
+        anyhow::anyhow!(
+          "Credential command `{}` printed invalid UTF-8",
+          self.program
+        )
+      })?;
+      Ok(Credentials::password(
+        password.trim_end_matches(['\r', '\n']),
+      ))
+    })
+  }
+}
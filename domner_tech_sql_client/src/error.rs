@@ -0,0 +1,23 @@
+use std::{fmt, time::Duration};
+
+/// Errors raised by the crate itself. Driver errors are passed through unchanged, so match on
+/// this type with `err.downcast_ref::<DbError>()`.
+#[derive(Debug)]
+pub enum DbError {
+  /// A command did not finish in time. Its server-side work was cancelled and the connection
+  /// discarded instead of being returned to the pool.
+  CommandTimeout(Duration),
+  /// Every connection of the named pool is checked out and it cannot open more.
+  PoolExhausted(String),
+}
+
+impl fmt::Display for DbError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      DbError::CommandTimeout(timeout) => write!(f, "Command timed out after {:?}", timeout),
+      DbError::PoolExhausted(pool) => write!(f, "Pool `{}` is empty", pool),
+    }
+  }
+}
+
+impl std::error::Error for DbError {}
@@ -0,0 +1,53 @@
+use anyhow::Result;
+use std::{fmt, future::Future, pin::Pin, sync::Arc};
+
+use crate::pool_manager::DbClient;
+
+/// Future returned by a [`ConnectionHook`].
+pub type HookFuture<'a> = Pin<Box<dyn Future<Output = Result<()>> + Send + 'a>>;
+
+/// Async callback run by the pool on one connection. Returning an error rejects the connection,
+/// which is closed instead of being used.
+pub type ConnectionHook = Arc<dyn for<'a> Fn(&'a mut DbClient) -> HookFuture<'a> + Send + Sync>;
+
+/// Callbacks run over a connection's life, set with
+/// [`PoolConfig::after_connect`](crate::config::PoolConfig::after_connect),
+/// [`before_acquire`](crate::config::PoolConfig::before_acquire) and
+/// [`after_release`](crate::config::PoolConfig::after_release).
+#[derive(Clone, Default)]
+pub struct PoolHooks {
+  /// Run once on every new connection, for session setup (`SET TIME ZONE`, `SET search_path`,
+  /// `SET ARITHABORT ON`, ...). A rejected connection fails the connect.
+  pub after_connect: Option<ConnectionHook>,
+  /// Run on an idle connection before it is handed out. A rejected connection is closed and the
+  /// next one is tried.
+  pub before_acquire: Option<ConnectionHook>,
+  /// Run on a connection coming back to the pool, to reset its state (`DISCARD ALL`, ...). A
+  /// rejected connection is closed instead of being pooled. The hook runs as a task on the
+  /// current runtime and the connection counts as in use until it is done, so closing the pool
+  /// waits for it. Released outside a runtime, the connection is closed without the hook.
+  pub after_release: Option<ConnectionHook>,
+}
+
+impl fmt::Debug for PoolHooks {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let set = |hook: &Option<ConnectionHook>| hook.as_ref().map(|_| "<hook>");
+    f.debug_struct("PoolHooks")
+      .field("after_connect", &set(&self.after_connect))
+      .field("before_acquire", &set(&self.before_acquire))
+      .field("after_release", &set(&self.after_release))
+      .finish()
+  }
+}
+
+/// Hook running `sql` as a batch on the connection, e.g. `"SET TIME ZONE 'UTC'"` after connect
+/// or `"DISCARD ALL"` after release
+pub fn execute(
+  sql: impl Into<String>,
+) -> impl for<'a> Fn(&'a mut DbClient) -> HookFuture<'a> + Send + Sync + 'static {
+  let sql: Arc<str> = sql.into().into();
+  move |client| {
+    let sql = sql.clone();
+    Box::pin(async move { client.batch_execute(&sql).await })
+  }
+}
@@ -0,0 +1,172 @@
+use anyhow::Result;
+use std::{future::Future, sync::Arc};
+
+use crate::CommandType;
+use crate::pool_manager::{DbPool, PooledClient};
+use crate::types::UnifiedToSql;
+
+#[cfg(feature = "tracing")]
+use tracing::{Instrument, Span, field};
+
+/// Span around one [`SqlRepo`](crate::SqlRepo) call or connection acquire.
+///
+/// Failed calls are counted in the pool's statistics; the span itself compiles to nothing
+/// unless the `tracing` feature is enabled.
+pub(crate) struct OpSpan {
+  pool: Option<Arc<DbPool>>,
+  #[cfg(feature = "tracing")]
+  span: Span,
+}
+
+impl OpSpan {
+  /// Span for a `SqlRepo` call. Parameter values are only recorded when the pool opted in with
+  /// [`PoolOptions::log_parameters`](crate::pool_manager::PoolOptions::log_parameters).
+  pub(crate) fn call(
+    pooled_client: &PooledClient,
+    operation: &'static str,
+    cmd_type: Option<CommandType>,
+    statement: &str,
+    params: &[&dyn UnifiedToSql],
+  ) -> Self {
+    #[cfg(feature = "tracing")]
+    {
+      let span = tracing::info_span!(
+        "sql_repo",
+        db.operation = operation,
+        db.pool = %pooled_client.name,
+        db.system = field::Empty,
+        db.command_type = field::Empty,
+        db.statement = statement,
+        db.param_count = params.len(),
+        db.params = field::Empty,
+        db.rows = field::Empty,
+        elapsed_ms = field::Empty,
+        error = field::Empty,
+      );
+      if let Some(cmd_type) = cmd_type {
+        span.record("db.command_type", field::debug(cmd_type));
+      }
+      if let Some(client) = pooled_client.client.as_ref() {
+        span.record("db.system", client.client_type().system_name());
+        if pooled_client.pool.config.options.log_parameters {
+          span.record("db.params", field::debug(describe_params(client, params)));
+        }
+      }
+      Self {
+        pool: Some(pooled_client.pool.clone()),
+        span,
+      }
+    }
+    #[cfg(not(feature = "tracing"))]
+    {
+      let _ = (operation, cmd_type, statement, params);
+      Self {
+        pool: Some(pooled_client.pool.clone()),
+      }
+    }
+  }
+
+  /// Span for checking a connection out of a pool.
+  pub(crate) fn acquire(pool_name: &str) -> Self {
+    #[cfg(feature = "tracing")]
+    {
+      let span = tracing::debug_span!(
+        "pool_acquire",
+        db.pool = pool_name,
+        elapsed_ms = field::Empty,
+        error = field::Empty,
+      );
+      Self { pool: None, span }
+    }
+    #[cfg(not(feature = "tracing"))]
+    {
+      let _ = pool_name;
+      Self { pool: None }
+    }
+  }
+
+  /// Run `fut` inside the span, then record its duration, row count and error.
+  pub(crate) async fn run<T>(
+    self,
+    fut: impl Future<Output = Result<T>>,
+    rows: impl FnOnce(&T) -> Option<usize>,
+  ) -> Result<T> {
+    #[cfg(feature = "tracing")]
+    let result = {
+      let start = std::time::Instant::now();
+      let result = fut.instrument(self.span.clone()).await;
+      self
+        .span
+        .record("elapsed_ms", start.elapsed().as_secs_f64() * 1000.0);
+      match &result {
+        Ok(value) => {
+          if let Some(rows) = rows(value) {
+            self.span.record("db.rows", rows);
+          }
+        }
+        Err(e) => {
+          self.span.record("error", field::display(e));
+          tracing::warn!(parent: &self.span, error = %e, "database call failed");
+        }
+      }
+      result
+    };
+    #[cfg(not(feature = "tracing"))]
+    let result = {
+      let _ = rows;
+      fut.await
+    };
+
+    if let (Err(_), Some(pool)) = (&result, &self.pool) {
+      pool.stats.error();
+    }
+    result
+  }
+}
+
+/// Render parameter values the way the driver will send them.
+#[cfg(feature = "tracing")]
+fn describe_params(
+  client: &crate::pool_manager::DbClient,
+  params: &[&dyn UnifiedToSql],
+) -> Vec<String> {
+  params
+    .iter()
+    .map(|param| {
+      #[cfg(feature = "mssql")]
+      if let Some(table) = param.table_valued() {
+        let rows: Vec<String> = table
+          .rows
+          .iter()
+          .map(|row| format!("({})", describe_params(client, row).join(", ")))
+          .collect();
+        return format!("{}[{}]", table.type_name, rows.join(", "));
+      }
+      match param.in_list() {
+        Some(items) => format!("[{}]", describe_params(client, &items.items).join(", ")),
+        None => describe_param(client, *param),
+      }
+    })
+    .collect()
+}
+
+#[cfg(feature = "tracing")]
+fn describe_param(client: &crate::pool_manager::DbClient, param: &dyn UnifiedToSql) -> String {
+  use crate::pool_manager::DbClient;
+  #[cfg(feature = "mssql")]
+  use crate::types::sql::mssql::ToSql;
+
+  match client {
+    #[cfg(feature = "mssql")]
+    DbClient::Mssql(_) => match param.mssql_json() {
+      Some(json) => json.map(|text| format!("{:?}", text.to_sql())),
+      None => param.to_mssql_param().map(|p| format!("{:?}", p.to_sql())),
+    }
+    .unwrap_or_else(|e| format!("<{}>", e)),
+    #[cfg(feature = "pgsql")]
+    DbClient::Pgsql(_) => param
+      .to_pgsql_param()
+      .map(|p| format!("{:?}", p))
+      .unwrap_or_else(|e| format!("<{}>", e)),
+  }
+}
@@ -1,18 +1,32 @@
+pub mod config;
+pub mod credentials;
+pub mod error;
+pub mod hooks;
+mod instrument;
+mod placeholders;
+pub mod pool_group;
 pub mod pool_manager;
+pub mod retry;
+pub mod stats;
+pub mod tls;
 pub mod types;
 
+use crate::instrument::OpSpan;
 use crate::pool_manager::DbClientType;
-use crate::pool_manager::{DbClient, DbRow, PooledClient};
+use crate::pool_manager::{DbClient, DbRow, PooledClient, with_timeout};
 use crate::types::UnifiedToSql;
 
-#[cfg(feature = "mssql")]
-use crate::types::sql::mssql;
-
 #[cfg(feature = "pgsql")]
 use crate::types::sql::pgsql;
 
 pub use anyhow::Result;
 
+use std::{future::Future, pin::Pin};
+
+/// Future returned by a [`SqlRepo::transaction`] closure. `Send`, so transactions can run in
+/// spawned tasks and web handlers.
+pub type TransactionFuture<'c, T> = Pin<Box<dyn Future<Output = Result<T>> + Send + 'c>>;
+
 #[derive(Debug, Clone, Copy)]
 pub enum CommandType {
   Text,
@@ -40,6 +54,29 @@ impl CommandType {
   }
 }
 
+/// Run `SqlRepo` call attempts until one succeeds or the pool's retry policy gives up.
+/// Each attempt is bounded by the call's command timeout.
+macro_rules! with_retry {
+  ($pooled_client:ident, $idempotent:expr, $attempt:expr) => {{
+    let timeout = $pooled_client.take_command_timeout();
+    let mut attempt = 1;
+    loop {
+      let result = match $pooled_client.prepare_attempt().await {
+        Ok(cancel) => {
+          let result = with_timeout(timeout, $attempt).await;
+          $pooled_client.finish_attempt(result, cancel).await
+        }
+        Err(e) => Err(e),
+      };
+      match result {
+        Ok(value) => break Ok(value),
+        Err(e) => retry::prepare_retry($pooled_client, e, &mut attempt, $idempotent).await?,
+      }
+      attempt += 1;
+    }
+  }};
+}
+
 pub struct SqlRepo;
 
 impl SqlRepo {
@@ -102,36 +139,75 @@ impl SqlRepo {
     }
   }
 
+  /// Execute a command and return the number of affected rows.
+  ///
+  /// Deadlocks and serialization failures are retried according to the pool's
+  /// [`RetryPolicy`](crate::retry::RetryPolicy); a broken connection is not, as the command may
+  /// already have been applied.
   pub async fn execute_command_none_query(
     pooled_client: &mut PooledClient,
     cmd_txt: &str,
     params: &[&dyn UnifiedToSql],
     cmd_type: CommandType,
   ) -> Result<u64> {
-    let client = pooled_client.client();
+    let span = OpSpan::call(
+      pooled_client,
+      "execute_command_none_query",
+      Some(cmd_type),
+      cmd_txt,
+      params,
+    );
+    span
+      .run(
+        async {
+          with_retry!(
+            pooled_client,
+            false,
+            Self::execute_command_none_query_once(pooled_client, cmd_txt, params, cmd_type)
+          )
+        },
+        |affected| Some(*affected as usize),
+      )
+      .await
+  }
+
+  async fn execute_command_none_query_once(
+    pooled_client: &mut PooledClient,
+    cmd_txt: &str,
+    params: &[&dyn UnifiedToSql],
+    cmd_type: CommandType,
+  ) -> Result<u64> {
+    let client = pooled_client.client()?;
     match client {
       //client.execute(&query, &params).await?;
       #[cfg(feature = "mssql")]
       DbClient::Mssql(c) => {
-        let mssql_params: Result<Vec<&dyn mssql::ToSql>> =
-          params.iter().map(|p| p.to_mssql_param()).collect();
         let query =
           Self::build_query_with_params(DbClientType::Mssql, cmd_txt, cmd_type, params.len());
-        Ok(c.execute(&query, mssql_params?.as_slice()).await?.total())
+        let (query, params) = placeholders::expand_params(DbClientType::Mssql, &query, params)?;
+        let json = types::mssql_json_texts(&params)?;
+        let mssql_params = types::mssql_params(&params, &json);
+        Ok(c.execute(query, mssql_params?.as_slice()).await?.total())
       }
       #[cfg(feature = "pgsql")]
       DbClient::Pgsql(c) => {
-        let pg_params: Result<Vec<&(dyn pgsql::types::ToSql + Sync)>> =
-          params.iter().map(|p| p.to_pgsql_param()).collect();
         let query =
           Self::build_query_with_params(DbClientType::Pgsql, cmd_txt, cmd_type, params.len());
-        Ok(c.execute(&query, pg_params?.as_slice()).await?)
+        let (query, params) = placeholders::expand_params(DbClientType::Pgsql, &query, params)?;
+        let pg_params: Result<Vec<&(dyn pgsql::types::ToSql + Sync)>> =
+          params.iter().map(|p| p.to_pgsql_param()).collect();
+        Ok(c.execute(&*query, pg_params?.as_slice()).await?)
       }
       #[cfg(not(any(feature = "mssql", feature = "pgsql")))]
       _ => return Err(anyhow::anyhow!("No database feature enabled.")),
     }
   }
 
+  /// Insert all `entities` with a single multi-row `INSERT` statement.
+  ///
+  /// Every value is a parameter, so the call fails without reaching the server when there are
+  /// more than 2098 on MSSQL or 65535 on PostgreSQL; larger sets have to be split over several
+  /// calls. Retried like [`SqlRepo::execute_command_none_query`].
   pub async fn execute_bulk_insert(
     pooled_client: &mut PooledClient,
     table: &str,
@@ -142,23 +218,48 @@ impl SqlRepo {
       return Ok(0);
     }
 
-    let client = pooled_client.client();
+    let statement = format!("INSERT INTO {} ({})", table, columns.join(", "));
+    let span = OpSpan::call(pooled_client, "execute_bulk_insert", None, &statement, &[]);
+    span
+      .run(
+        async {
+          with_retry!(
+            pooled_client,
+            false,
+            Self::execute_bulk_insert_once(pooled_client, table, columns, entities)
+          )
+        },
+        |affected| Some(*affected as usize),
+      )
+      .await
+  }
+
+  async fn execute_bulk_insert_once(
+    pooled_client: &mut PooledClient,
+    table: &str,
+    columns: &[&str],
+    entities: &[&[&dyn UnifiedToSql]],
+  ) -> Result<u64> {
+    let client = pooled_client.client()?;
 
     match client {
       #[cfg(feature = "mssql")]
       DbClient::Mssql(c) => {
         let mut values = Vec::new();
-        let mut flat_params: Vec<&dyn mssql::ToSql> = Vec::new();
+        let mut params: Vec<&dyn UnifiedToSql> = Vec::new();
 
         for (row_idx, entity) in entities.iter().enumerate() {
           let mut row_placeholders = Vec::new();
           for (col_idx, param) in entity.iter().enumerate() {
             let param_index = row_idx * entity.len() + col_idx + 1;
             row_placeholders.push(format!("@P{}", param_index));
-            flat_params.push(param.to_mssql_param()?);
+            params.push(*param);
           }
           values.push(format!("({})", row_placeholders.join(", ")));
         }
+        placeholders::check_param_count(DbClientType::Mssql, params.len())?;
+        let json = types::mssql_json_texts(&params)?;
+        let flat_params = types::mssql_params(&params, &json)?;
 
         let query = format!(
           "INSERT INTO {} ({}) VALUES {}",
@@ -183,6 +284,7 @@ impl SqlRepo {
           }
           values.push(format!("({})", row_placeholders.join(", ")));
         }
+        placeholders::check_param_count(DbClientType::Pgsql, flat_params.len())?;
 
         let query = format!(
           "INSERT INTO {} ({}) VALUES {}",
@@ -198,6 +300,10 @@ impl SqlRepo {
     }
   }
 
+  /// Execute a query and map every returned row.
+  ///
+  /// Queries are treated as idempotent, so every transient error class enabled in the pool's
+  /// [`RetryPolicy`](crate::retry::RetryPolicy) is retried, reconnecting when the connection broke.
   pub async fn execute_command_query<T>(
     pooled_client: &mut PooledClient,
     cmd_txt: &str,
@@ -209,17 +315,44 @@ impl SqlRepo {
       return Ok(Vec::new());
     }
 
-    let client = pooled_client.client();
+    let span = OpSpan::call(
+      pooled_client,
+      "execute_command_query",
+      Some(cmd_type),
+      cmd_txt,
+      params,
+    );
+    span
+      .run(
+        async {
+          with_retry!(
+            pooled_client,
+            true,
+            Self::execute_command_query_once(pooled_client, cmd_txt, params, cmd_type, &map_rows)
+          )
+        },
+        |rows| Some(rows.len()),
+      )
+      .await
+  }
+
+  async fn execute_command_query_once<T>(
+    pooled_client: &mut PooledClient,
+    cmd_txt: &str,
+    params: &[&dyn UnifiedToSql],
+    cmd_type: CommandType,
+    map_rows: &impl Fn(&DbRow) -> T,
+  ) -> Result<Vec<T>> {
+    let client = pooled_client.client()?;
 
     let db_rows = match client {
       #[cfg(feature = "mssql")]
       DbClient::Mssql(c) => {
-        use crate::pool_manager::DbClientType;
-
-        let mssql_params: Result<Vec<&dyn mssql::ToSql>> =
-          params.iter().map(|p| p.to_mssql_param()).collect();
         let query =
           Self::build_query_with_params(DbClientType::Mssql, cmd_txt, cmd_type, params.len());
+        let (query, params) = placeholders::expand_params(DbClientType::Mssql, &query, params)?;
+        let json = types::mssql_json_texts(&params)?;
+        let mssql_params = types::mssql_params(&params, &json);
         let stream = c.query(query, mssql_params?.as_slice()).await?;
         let rows = stream.into_results().await?;
         let mut results: Vec<T> = Vec::new();
@@ -231,12 +364,12 @@ impl SqlRepo {
 
       #[cfg(feature = "pgsql")]
       DbClient::Pgsql(c) => {
-        let pg_params: Result<Vec<&(dyn pgsql::types::ToSql + Sync)>> =
-          params.iter().map(|p| p.to_pgsql_param()).collect();
         let query =
           Self::build_query_with_params(DbClientType::Pgsql, cmd_txt, cmd_type, params.len());
-        let rows = c.query(&query, pg_params?.as_slice()).await?;
-        println!("{} {}", query, rows.len());
+        let (query, params) = placeholders::expand_params(DbClientType::Pgsql, &query, params)?;
+        let pg_params: Result<Vec<&(dyn pgsql::types::ToSql + Sync)>> =
+          params.iter().map(|p| p.to_pgsql_param()).collect();
+        let rows = c.query(&*query, pg_params?.as_slice()).await?;
         let mut results: Vec<T> = Vec::new();
         for row in &rows {
           results.push(map_rows(&DbRow::Pgsql(row)));
@@ -260,4 +393,170 @@ impl SqlRepo {
       Self::execute_command_query(pooled_client, cmd_txt, params, cmd_type, map_row).await?;
     Ok(rows.pop())
   }
+
+  /// Run `f` inside a transaction, committing when it succeeds and rolling back when it fails.
+  ///
+  /// Statements issued by `f` are not retried individually. Instead, when the transaction fails
+  /// with a transient error, it is rolled back and `f` is run again from the start according to
+  /// the pool's [`RetryPolicy`](crate::retry::RetryPolicy), on a fresh connection if the old one
+  /// broke. A connection lost while committing is never retried, as the outcome is unknown.
+  ///
+  /// ```ignore
+  /// let id = SqlRepo::transaction(&mut client, |client| {
+  ///   Box::pin(async move {
+  ///     SqlRepo::execute_command_none_query(client, "UPDATE ...", &[], CommandType::Text).await?;
+  ///     Ok(1)
+  ///   })
+  /// })
+  /// .await?;
+  /// ```
+  pub async fn transaction<T, F>(pooled_client: &mut PooledClient, mut f: F) -> Result<T>
+  where
+    F: for<'c> FnMut(&'c mut PooledClient) -> TransactionFuture<'c, T> + Send,
+    T: Send,
+  {
+    let span = OpSpan::call(pooled_client, "transaction", None, "", &[]);
+    span
+      .run(
+        async {
+          let timeout = pooled_client.take_command_timeout();
+          let mut attempt = 1;
+          loop {
+            match Self::transaction_once(pooled_client, &mut f, timeout).await {
+              Ok(value) => return Ok(value),
+              Err(TransactionError::Final(e)) => return Err(e),
+              Err(TransactionError::Retryable(e)) => {
+                retry::prepare_retry(pooled_client, e, &mut attempt, true).await?
+              }
+            }
+            attempt += 1;
+          }
+        },
+        |_| None,
+      )
+      .await
+  }
+
+  async fn transaction_once<T, F>(
+    pooled_client: &mut PooledClient,
+    f: &mut F,
+    timeout: Option<std::time::Duration>,
+  ) -> std::result::Result<T, TransactionError>
+  where
+    F: for<'c> FnMut(&'c mut PooledClient) -> TransactionFuture<'c, T> + Send,
+    T: Send,
+  {
+    let cancel = pooled_client
+      .prepare_attempt()
+      .await
+      .map_err(TransactionError::Retryable)?;
+    Self::batch_execute(pooled_client, TransactionStatement::Begin)
+      .await
+      .map_err(TransactionError::Retryable)?;
+
+    pooled_client.in_transaction = true;
+    let result = with_timeout(timeout, f(pooled_client)).await;
+    pooled_client.in_transaction = false;
+
+    let result = pooled_client.finish_attempt(result, cancel).await;
+    if pooled_client.client.is_none() {
+      // The connection was discarded after a timeout, which rolls the transaction back.
+      return result.map_err(TransactionError::Final);
+    }
+
+    match result {
+      Ok(value) => {
+        match Self::batch_execute(pooled_client, TransactionStatement::Commit).await {
+          Ok(()) => Ok(value),
+          // A transient failure reported by the server means nothing was committed.
+          Err(e)
+            if retry::RetryableError::classify(&e)
+              .is_some_and(|c| c != retry::RetryableError::ConnectionBroken) =>
+          {
+            Err(TransactionError::Retryable(e))
+          }
+          Err(e) => Err(TransactionError::Final(e)),
+        }
+      }
+      Err(e) => {
+        // The connection may already be gone; the original error is what matters.
+        let _ = Self::batch_execute(pooled_client, TransactionStatement::Rollback).await;
+        Err(TransactionError::Retryable(e))
+      }
+    }
+  }
+
+  async fn batch_execute(
+    pooled_client: &mut PooledClient,
+    statement: TransactionStatement,
+  ) -> Result<()> {
+    match pooled_client.client()? {
+      #[cfg(feature = "mssql")]
+      DbClient::Mssql(c) => {
+        let query = match statement {
+          TransactionStatement::Begin => "BEGIN TRANSACTION",
+          TransactionStatement::Commit => "COMMIT TRANSACTION",
+          TransactionStatement::Rollback => "IF @@TRANCOUNT > 0 ROLLBACK TRANSACTION",
+        };
+        c.simple_query(query).await?.into_results().await?;
+        Ok(())
+      }
+      #[cfg(feature = "pgsql")]
+      DbClient::Pgsql(c) => {
+        let query = match statement {
+          TransactionStatement::Begin => "BEGIN",
+          TransactionStatement::Commit => "COMMIT",
+          TransactionStatement::Rollback => "ROLLBACK",
+        };
+        Ok(c.batch_execute(query).await?)
+      }
+      #[cfg(not(any(feature = "mssql", feature = "pgsql")))]
+      _ => Err(anyhow::anyhow!("No database feature enabled.")),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum TransactionStatement {
+  Begin,
+  Commit,
+  Rollback,
+}
+
+enum TransactionError {
+  /// The transaction did not commit and may be retried.
+  Retryable(anyhow::Error),
+  /// The commit outcome is unknown, or the transaction timed out.
+  Final(anyhow::Error),
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// Compiles only if a transaction, with statements binding parameters, can be spawned
+  #[allow(dead_code)]
+  fn spawn_transaction(mut client: PooledClient) -> tokio::task::JoinHandle<Result<u64>> {
+    tokio::spawn(async move {
+      SqlRepo::transaction(&mut client, |client| {
+        Box::pin(async move {
+          let id = 7;
+          let name = String::from("x");
+          SqlRepo::execute_command_none_query(
+            client,
+            "UPDATE t SET name = $2 WHERE id = $1",
+            &[&id, &name],
+            CommandType::Text,
+          )
+          .await
+        })
+      })
+      .await
+    })
+  }
+
+  #[test]
+  fn transactions_are_send() {
+    let _ = spawn_transaction;
+  }
 }
@@ -0,0 +1,716 @@
+use anyhow::Result;
+use std::borrow::Cow;
+
+use crate::pool_manager::DbClientType;
+use crate::types::UnifiedToSql;
+
+/// Parameters of a statement after [`expand_params`]
+pub(crate) type Params<'p> = Cow<'p, [&'p dyn UnifiedToSql]>;
+
+/// New placeholders of a parameter
+enum Slot {
+  /// `count` placeholders from index `first`
+  Items { first: usize, count: usize },
+  /// An empty list; on MSSQL, the index of a typed `NULL` to select no rows from
+  Empty { null: Option<usize> },
+  /// The `var`th table variable
+  #[cfg(feature = "mssql")]
+  Table { var: usize },
+}
+
+/// A table variable filled from placeholders `first..first + columns * rows`
+#[cfg(feature = "mssql")]
+struct Table {
+  /// Bracket-quoted, see [`quote_type_name`]
+  type_name: String,
+  first: usize,
+  columns: usize,
+  rows: usize,
+}
+
+/// Most rows one `INSERT ... VALUES` statement can insert on MSSQL
+#[cfg(feature = "mssql")]
+const MAX_VALUES_ROWS: usize = 1000;
+
+/// Replace the placeholder of every [`InList`](crate::types::InList) parameter with one
+/// placeholder per item and renumber the others. Placeholders inside string literals, quoted
+/// identifiers and comments are left alone. Statements without lists or tables are returned
+/// unchanged.
+///
+/// An empty list becomes `IN (SELECT @Pn WHERE 1 = 0)` on MSSQL, with `@Pn` a `NULL` of the item
+/// type, and `= ANY('{}')` (`<> ALL('{}')` after `NOT`) on PostgreSQL, so `IN` matches no row
+/// and `NOT IN` every row.
+///
+/// The placeholder of a [`TableValuedParam`](crate::types::TableValuedParam) becomes a table
+/// variable that statements put in front of the batch declare and fill, one placeholder per value.
+pub(crate) fn expand_params<'q, 'p>(
+  db_type: DbClientType,
+  sql: &'q str,
+  params: &'p [&'p dyn UnifiedToSql],
+) -> Result<(Cow<'q, str>, Params<'p>)> {
+  let lists: Vec<_> = params.iter().map(|param| param.in_list()).collect();
+  #[cfg(feature = "mssql")]
+  let has_tables = params.iter().any(|param| param.table_valued().is_some());
+  #[cfg(not(feature = "mssql"))]
+  let has_tables = false;
+  if !has_tables && lists.iter().all(Option::is_none) {
+    return Ok((Cow::Borrowed(sql), Cow::Borrowed(params)));
+  }
+
+  let mut expanded = Vec::new();
+  let mut slots = Vec::with_capacity(params.len());
+  #[cfg(feature = "mssql")]
+  let mut tables = Vec::new();
+  for (param, list) in params.iter().zip(lists) {
+    let first = expanded.len() + 1;
+    #[cfg(feature = "mssql")]
+    if let Some(table) = param.table_valued().filter(|_| is_mssql(db_type)) {
+      let columns = table.rows.first().map_or(0, |row| row.len());
+      if columns == 0 && !table.rows.is_empty() {
+        return Err(anyhow::anyhow!(
+          "Rows of the `{}` table have no values",
+          table.type_name
+        ));
+      }
+      if let Some(n) = table.rows.iter().position(|row| row.len() != columns) {
+        return Err(anyhow::anyhow!(
+          "Row {} of the `{}` table has {} values, expected {}",
+          n + 1,
+          table.type_name,
+          table.rows[n].len(),
+          columns
+        ));
+      }
+      tables.push(Table {
+        type_name: quote_type_name(table.type_name)?,
+        first,
+        columns,
+        rows: table.rows.len(),
+      });
+      slots.push(Slot::Table { var: tables.len() });
+      expanded.extend(table.rows.iter().flat_map(|row| row.iter().copied()));
+      continue;
+    }
+    match list {
+      Some(list) if list.items.is_empty() => {
+        let null = is_mssql(db_type).then(|| {
+          expanded.push(list.null);
+          first
+        });
+        slots.push(Slot::Empty { null });
+      }
+      Some(list) => {
+        slots.push(Slot::Items {
+          first,
+          count: list.items.len(),
+        });
+        expanded.extend(list.items);
+      }
+      None => {
+        slots.push(Slot::Items { first, count: 1 });
+        expanded.push(*param);
+      }
+    }
+  }
+  check_param_count(db_type, expanded.len())?;
+
+  let mut out = String::with_capacity(sql.len());
+  let mut copied = 0;
+  let mut scanner = Scanner {
+    db_type,
+    sql,
+    pos: 0,
+  };
+  while let Some((start, end, index)) = scanner.next_placeholder() {
+    let Some(slot) = index.checked_sub(1).and_then(|i| slots.get(i)) else {
+      // Out of range; left for the driver to report
+      continue;
+    };
+    match *slot {
+      Slot::Items { first, count } => {
+        let placeholders: Vec<String> = (first..first + count)
+          .map(|n| placeholder(db_type, n))
+          .collect();
+        out.push_str(&sql[copied..start]);
+        out.push_str(&placeholders.join(", "));
+        copied = end;
+      }
+      Slot::Empty { null: Some(null) } => {
+        out.push_str(&sql[copied..start]);
+        out.push_str(&format!(
+          "SELECT {} WHERE 1 = 0",
+          placeholder(db_type, null)
+        ));
+        copied = end;
+      }
+      Slot::Empty { null: None } => {
+        let Some((in_start, in_end, negated)) =
+          in_clause(sql, start, end).filter(|c| c.0 >= copied)
+        else {
+          return Err(anyhow::anyhow!(
+            "The empty list bound to ${} must be used as `IN (${})` or `NOT IN (${})`",
+            index,
+            index,
+            index
+          ));
+        };
+        out.push_str(&sql[copied..in_start]);
+        out.push_str(if negated {
+          "<> ALL('{}')"
+        } else {
+          "= ANY('{}')"
+        });
+        copied = in_end;
+        scanner.pos = scanner.pos.max(in_end);
+      }
+      #[cfg(feature = "mssql")]
+      Slot::Table { var } => {
+        out.push_str(&sql[copied..start]);
+        out.push_str(&table_var(var));
+        copied = end;
+      }
+    }
+  }
+  out.push_str(&sql[copied..]);
+  #[cfg(feature = "mssql")]
+  if !tables.is_empty() {
+    out.insert_str(0, &declare_tables(&tables));
+  }
+  Ok((Cow::Owned(out), Cow::Owned(expanded)))
+}
+
+/// Statements that declare and fill the table variables. `NOCOUNT` is on while they run so the
+/// rows inserted are not counted as rows the statement affected.
+#[cfg(feature = "mssql")]
+fn declare_tables(tables: &[Table]) -> String {
+  let mut batch = String::from("DECLARE @__tvp_nocount INT = @@OPTIONS & 512;\nSET NOCOUNT ON;\n");
+  for (n, table) in tables.iter().enumerate() {
+    let var = table_var(n + 1);
+    batch.push_str(&format!("DECLARE {} {};\n", var, table.type_name));
+    let rows: Vec<String> = (0..table.rows)
+      .map(|row| {
+        let first = table.first + row * table.columns;
+        let values: Vec<String> = (first..first + table.columns)
+          .map(|index| placeholder(DbClientType::Mssql, index))
+          .collect();
+        format!("({})", values.join(", "))
+      })
+      .collect();
+    for chunk in rows.chunks(MAX_VALUES_ROWS) {
+      batch.push_str(&format!(
+        "INSERT INTO {} VALUES {};\n",
+        var,
+        chunk.join(", ")
+      ));
+    }
+  }
+  batch.push_str("IF @__tvp_nocount = 0 SET NOCOUNT OFF;\n");
+  batch
+}
+
+/// A table type name with each part bracket-quoted, so it can only name a type. Parts are plain
+/// identifiers or already bracketed, e.g. `dbo.OrderLine` or `[dbo].[Order Line]`.
+#[cfg(feature = "mssql")]
+fn quote_type_name(name: &str) -> Result<String> {
+  let invalid = || anyhow::anyhow!("Invalid table type name `{}`", name);
+  let mut parts = Vec::new();
+  let mut rest = name;
+  loop {
+    let part = if let Some(bracketed) = rest.strip_prefix('[') {
+      // `]]` is an escaped `]`
+      let bytes = bracketed.as_bytes();
+      let mut end = 0;
+      loop {
+        match bytes.get(end) {
+          Some(b']') if bytes.get(end + 1) == Some(&b']') => end += 2,
+          Some(b']') => break,
+          Some(_) => end += 1,
+          None => return Err(invalid()),
+        }
+      }
+      rest = &bracketed[end + 1..];
+      bracketed[..end].replace("]]", "]")
+    } else {
+      let len = rest.find('.').unwrap_or(rest.len());
+      let ident = &rest[..len];
+      let valid = ident
+        .chars()
+        .all(|c| c.is_alphanumeric() || matches!(c, '_' | '@' | '#' | '$'))
+        && !ident.starts_with(|c: char| c.is_ascii_digit());
+      if !valid {
+        return Err(invalid());
+      }
+      rest = &rest[len..];
+      ident.to_string()
+    };
+    if part.is_empty() {
+      return Err(invalid());
+    }
+    parts.push(format!("[{}]", part.replace(']', "]]")));
+    match rest.strip_prefix('.') {
+      Some(next) => rest = next,
+      None if rest.is_empty() => break,
+      None => return Err(invalid()),
+    }
+  }
+  // `type` or `schema.type`
+  if parts.len() > 2 {
+    return Err(invalid());
+  }
+  Ok(parts.join("."))
+}
+
+#[cfg(feature = "mssql")]
+fn table_var(var: usize) -> String {
+  format!("@__tvp{}", var)
+}
+
+/// Byte range of the `[NOT] IN (placeholder)` around the placeholder at `start..end`, and
+/// whether it has `NOT`
+fn in_clause(sql: &str, start: usize, end: usize) -> Option<(usize, usize, bool)> {
+  let bytes = sql.as_bytes();
+  let close = end
+    + bytes[end..]
+      .iter()
+      .take_while(|b| b.is_ascii_whitespace())
+      .count();
+  if bytes.get(close) != Some(&b')') {
+    return None;
+  }
+  let open = trim_end(bytes, start).checked_sub(1)?;
+  if bytes[open] != b'(' {
+    return None;
+  }
+  let in_start = keyword_before(bytes, trim_end(bytes, open), b"in")?;
+  Some(
+    match keyword_before(bytes, trim_end(bytes, in_start), b"not") {
+      Some(not_start) => (not_start, close + 1, true),
+      None => (in_start, close + 1, false),
+    },
+  )
+}
+
+/// Start of `keyword` if it ends at `end` as a whole word
+fn keyword_before(bytes: &[u8], end: usize, keyword: &[u8]) -> Option<usize> {
+  let start = end.checked_sub(keyword.len())?;
+  let word = bytes[start..end].eq_ignore_ascii_case(keyword);
+  (word && (start == 0 || !is_ident(bytes[start - 1]))).then_some(start)
+}
+
+/// `end` moved back over whitespace
+fn trim_end(bytes: &[u8], end: usize) -> usize {
+  end
+    - bytes[..end]
+      .iter()
+      .rev()
+      .take_while(|b| b.is_ascii_whitespace())
+      .count()
+}
+
+/// Fail before reaching the server if a statement binds more parameters than it allows
+pub(crate) fn check_param_count(db_type: DbClientType, count: usize) -> Result<()> {
+  let limit = max_params(db_type);
+  if count > limit {
+    return Err(anyhow::anyhow!(
+      "Parameters expand to {} placeholders, over the {} limit of {}",
+      count,
+      db_type.system_name(),
+      limit
+    ));
+  }
+  Ok(())
+}
+
+/// Most parameters one statement can bind
+fn max_params(db_type: DbClientType) -> usize {
+  match db_type {
+    // 2100 per RPC call, two of which `sp_executesql` uses for the statement and declarations
+    #[cfg(feature = "mssql")]
+    DbClientType::Mssql => 2098,
+    #[cfg(feature = "pgsql")]
+    DbClientType::Pgsql => u16::MAX as usize,
+  }
+}
+
+fn placeholder(db_type: DbClientType, index: usize) -> String {
+  match db_type {
+    #[cfg(feature = "mssql")]
+    DbClientType::Mssql => format!("@P{}", index),
+    #[cfg(feature = "pgsql")]
+    DbClientType::Pgsql => format!("${}", index),
+  }
+}
+
+/// Finds `@Pn` (MSSQL) or `$n` (PostgreSQL) placeholders outside literals and comments
+struct Scanner<'q> {
+  db_type: DbClientType,
+  sql: &'q str,
+  pos: usize,
+}
+
+impl Scanner<'_> {
+  /// Byte range and 1-based index of the next placeholder
+  fn next_placeholder(&mut self) -> Option<(usize, usize, usize)> {
+    let bytes = self.sql.as_bytes();
+    while self.pos < bytes.len() {
+      let start = self.pos;
+      let after_ident = start > 0 && is_ident(bytes[start - 1]);
+      match bytes[start] {
+        quote @ (b'\'' | b'"') => self.skip_past(start + 1, &[quote]),
+        b'[' if self.is_mssql() => self.skip_past(start + 1, b"]"),
+        b'-' if bytes.get(start + 1) == Some(&b'-') => self.skip_past(start + 2, b"\n"),
+        b'/' if bytes.get(start + 1) == Some(&b'*') => self.skip_past(start + 2, b"*/"),
+        b'@'
+          if self.is_mssql()
+            && !after_ident
+            && matches!(bytes.get(start + 1), Some(b'P' | b'p')) =>
+        {
+          if let Some(found) = self.index_at(start, start + 2) {
+            return Some(found);
+          }
+        }
+        b'$' if !self.is_mssql() && !after_ident => {
+          if bytes.get(start + 1).is_some_and(u8::is_ascii_digit) {
+            if let Some(found) = self.index_at(start, start + 1) {
+              return Some(found);
+            }
+          } else {
+            self.skip_dollar_quote(start);
+          }
+        }
+        _ => self.pos += 1,
+      }
+    }
+    None
+  }
+
+  /// Placeholder starting at `start` whose digits begin at `digits`
+  fn index_at(&mut self, start: usize, digits: usize) -> Option<(usize, usize, usize)> {
+    let bytes = self.sql.as_bytes();
+    let end = digits
+      + bytes[digits..]
+        .iter()
+        .take_while(|b| b.is_ascii_digit())
+        .count();
+    self.pos = end.max(start + 1);
+    if end == digits || bytes.get(end).is_some_and(|&b| is_ident(b)) {
+      return None;
+    }
+    let index = self.sql[digits..end].parse().ok()?;
+    Some((start, end, index))
+  }
+
+  /// Move past the next `delimiter` at or after `from`, or to the end
+  fn skip_past(&mut self, from: usize, delimiter: &[u8]) {
+    let bytes = self.sql.as_bytes();
+    self.pos = bytes[from.min(bytes.len())..]
+      .windows(delimiter.len())
+      .position(|window| window == delimiter)
+      .map_or(bytes.len(), |offset| from + offset + delimiter.len());
+  }
+
+  /// Skip a `$tag$ ... $tag$` string if one starts at `start`
+  fn skip_dollar_quote(&mut self, start: usize) {
+    let bytes = self.sql.as_bytes();
+    let tag_len = bytes[start + 1..]
+      .iter()
+      .take_while(|&&b| is_ident(b) && b != b'$')
+      .count();
+    let close = start + 1 + tag_len;
+    if bytes.get(close) == Some(&b'$') {
+      let delimiter = &bytes[start..=close];
+      self.skip_past(close + 1, delimiter);
+    } else {
+      self.pos = start + 1;
+    }
+  }
+
+  fn is_mssql(&self) -> bool {
+    is_mssql(self.db_type)
+  }
+}
+
+fn is_mssql(db_type: DbClientType) -> bool {
+  match db_type {
+    #[cfg(feature = "mssql")]
+    DbClientType::Mssql => true,
+    #[cfg(feature = "pgsql")]
+    DbClientType::Pgsql => false,
+  }
+}
+
+fn is_ident(b: u8) -> bool {
+  b.is_ascii_alphanumeric() || matches!(b, b'_' | b'$' | b'@' | b'#')
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  #[cfg(any(feature = "mssql", feature = "pgsql"))]
+  use crate::types::InList;
+
+  #[cfg(any(feature = "mssql", feature = "pgsql"))]
+  fn expand(db_type: DbClientType, sql: &str, params: &[&dyn UnifiedToSql]) -> (String, usize) {
+    let (sql, params) = expand_params(db_type, sql, params).unwrap();
+    (sql.into_owned(), params.len())
+  }
+
+  #[cfg(feature = "pgsql")]
+  #[test]
+  fn pgsql_lists_are_expanded_outside_literals_and_comments() {
+    let sql = "SELECT '$1', \"$1\", $$ $1 $$, $tag$ $1 $tag$ -- $1\n\
+      FROM t /* $1 */ WHERE a IN ($1) AND b = $2";
+    let (sql, count) = expand(DbClientType::Pgsql, sql, &[&InList(&[1, 2]), &7]);
+    assert_eq!(
+      sql,
+      "SELECT '$1', \"$1\", $$ $1 $$, $tag$ $1 $tag$ -- $1\n\
+      FROM t /* $1 */ WHERE a IN ($1, $2) AND b = $3"
+    );
+    assert_eq!(count, 3);
+  }
+
+  #[cfg(feature = "mssql")]
+  #[test]
+  fn mssql_lists_are_expanded_outside_literals_and_comments() {
+    let sql = "SELECT '@P1', [@P1], \"@P1\", x@P1 -- @P1\n\
+      FROM t /* @P1 */ WHERE a IN (@P1) AND b = @P2 AND c = @p2";
+    let (sql, count) = expand(DbClientType::Mssql, sql, &[&InList(&[1, 2, 3]), &7]);
+    assert_eq!(
+      sql,
+      "SELECT '@P1', [@P1], \"@P1\", x@P1 -- @P1\n\
+      FROM t /* @P1 */ WHERE a IN (@P1, @P2, @P3) AND b = @P4 AND c = @P4"
+    );
+    assert_eq!(count, 4);
+  }
+
+  #[cfg(feature = "pgsql")]
+  #[test]
+  fn repeated_placeholders_share_their_expansion() {
+    let (sql, count) = expand(
+      DbClientType::Pgsql,
+      "SELECT $2 WHERE a IN ($1) OR b IN ($1)",
+      &[&InList(&["x", "y"]), &true],
+    );
+    assert_eq!(sql, "SELECT $3 WHERE a IN ($1, $2) OR b IN ($1, $2)");
+    assert_eq!(count, 3);
+  }
+
+  #[cfg(any(feature = "mssql", feature = "pgsql"))]
+  #[test]
+  fn statements_without_lists_are_unchanged() {
+    #[cfg(feature = "pgsql")]
+    let db_type = DbClientType::Pgsql;
+    #[cfg(not(feature = "pgsql"))]
+    let db_type = DbClientType::Mssql;
+    let (sql, params) = expand_params(db_type, "SELECT 1", &[&1]).unwrap();
+    assert!(matches!(sql, Cow::Borrowed(_)));
+    assert!(matches!(params, Cow::Borrowed(_)));
+  }
+
+  #[cfg(feature = "pgsql")]
+  #[test]
+  fn pgsql_empty_lists() {
+    let empty = InList::<i32>(&[]);
+    let (sql, count) = expand(
+      DbClientType::Pgsql,
+      "SELECT * FROM t WHERE a IN ($1) AND b NOT IN ( $1 ) AND c = $2",
+      &[&empty, &7],
+    );
+    assert_eq!(
+      sql,
+      "SELECT * FROM t WHERE a = ANY('{}') AND b <> ALL('{}') AND c = $1"
+    );
+    assert_eq!(count, 1);
+  }
+
+  #[cfg(feature = "mssql")]
+  #[test]
+  fn mssql_empty_lists() {
+    let empty = InList::<i32>(&[]);
+    let (sql, count) = expand(
+      DbClientType::Mssql,
+      "SELECT * FROM t WHERE a IN (@P1) AND b NOT IN (@P1) AND c = @P2",
+      &[&empty, &7],
+    );
+    assert_eq!(
+      sql,
+      "SELECT * FROM t WHERE a IN (SELECT @P1 WHERE 1 = 0) \
+       AND b NOT IN (SELECT @P1 WHERE 1 = 0) AND c = @P2"
+    );
+    // A typed NULL for the empty list, then the renumbered parameter
+    assert_eq!(count, 2);
+  }
+
+  #[cfg(feature = "pgsql")]
+  #[test]
+  fn empty_lists_outside_in_are_rejected() {
+    let empty = InList::<i32>(&[]);
+    for sql in [
+      "SELECT $1",
+      "SELECT * FROM t WHERE a = $1",
+      "SELECT * FROM t WHERE a IN ($1, 2)",
+    ] {
+      let err = expand_params(DbClientType::Pgsql, sql, &[&empty])
+        .err()
+        .unwrap();
+      assert!(err.to_string().contains("`IN ($1)`"), "{}", err);
+    }
+  }
+
+  #[cfg(feature = "mssql")]
+  #[test]
+  fn mssql_parameter_limit() {
+    let items = vec![1; 2098];
+    let (_, count) = expand(DbClientType::Mssql, "WHERE a IN (@P1)", &[&InList(&items)]);
+    assert_eq!(count, 2098);
+    let err = expand_params(
+      DbClientType::Mssql,
+      "WHERE a IN (@P1) AND b = @P2",
+      &[&InList(&items), &1],
+    )
+    .err()
+    .unwrap();
+    assert!(err.to_string().contains("2099 placeholders"), "{}", err);
+    assert!(err.to_string().contains("limit of 2098"), "{}", err);
+  }
+
+  #[cfg(feature = "mssql")]
+  #[test]
+  fn bulk_inserts_share_the_limit() {
+    assert!(check_param_count(DbClientType::Mssql, 2098).is_ok());
+    let err = check_param_count(DbClientType::Mssql, 3000).unwrap_err();
+    assert!(err.to_string().contains("3000 placeholders"), "{}", err);
+  }
+
+  #[cfg(feature = "pgsql")]
+  #[test]
+  fn pgsql_parameter_limit() {
+    let items = vec![1; 65536];
+    let err = expand_params(DbClientType::Pgsql, "WHERE a IN ($1)", &[&InList(&items)])
+      .err()
+      .unwrap();
+    assert!(err.to_string().contains("limit of 65535"), "{}", err);
+  }
+
+  #[cfg(feature = "mssql")]
+  mod tables {
+    use super::*;
+    use crate::types::TableValuedParam;
+
+    const PREFIX: &str = "DECLARE @__tvp_nocount INT = @@OPTIONS & 512;\nSET NOCOUNT ON;\n";
+    const SUFFIX: &str = "IF @__tvp_nocount = 0 SET NOCOUNT OFF;\n";
+
+    #[test]
+    fn rows_fill_a_table_variable_and_later_placeholders_are_renumbered() {
+      let rows: &[&[&dyn UnifiedToSql]] = &[&[&"A-1", &2], &[&"B-7", &1]];
+      let lines = TableValuedParam::new("dbo.OrderLine", rows);
+      let (sql, count) = expand(
+        DbClientType::Mssql,
+        "EXEC dbo.PlaceOrder @P1, @P2, @P3",
+        &[&42, &lines, &true],
+      );
+      assert_eq!(
+        sql,
+        format!(
+          "{}DECLARE @__tvp1 [dbo].[OrderLine];\n\
+           INSERT INTO @__tvp1 VALUES (@P2, @P3), (@P4, @P5);\n{}\
+           EXEC dbo.PlaceOrder @P1, @__tvp1, @P6",
+          PREFIX, SUFFIX
+        )
+      );
+      assert_eq!(count, 6);
+    }
+
+    #[test]
+    fn empty_tables_are_declared_without_rows() {
+      let lines = TableValuedParam::new("OrderLine", &[]);
+      let (sql, count) = expand(
+        DbClientType::Mssql,
+        "EXEC dbo.Place @P1, @P2",
+        &[&lines, &1],
+      );
+      assert_eq!(
+        sql,
+        format!(
+          "{}DECLARE @__tvp1 [OrderLine];\n{}EXEC dbo.Place @__tvp1, @P1",
+          PREFIX, SUFFIX
+        )
+      );
+      assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn inserts_are_chunked_by_1000_rows() {
+      let row: &[&dyn UnifiedToSql] = &[&1];
+      let rows = vec![row; 1001];
+      let ids = TableValuedParam::new("dbo.Ids", &rows);
+      let (sql, count) = expand(DbClientType::Mssql, "EXEC dbo.Load @P1", &[&ids]);
+      let inserts: Vec<&str> = sql
+        .lines()
+        .filter(|line| line.starts_with("INSERT"))
+        .collect();
+      assert_eq!(inserts.len(), 2);
+      assert_eq!(inserts[0].matches("(@P").count(), 1000);
+      assert!(inserts[0].ends_with("(@P1000);"));
+      assert_eq!(inserts[1], "INSERT INTO @__tvp1 VALUES (@P1001);");
+      assert_eq!(count, 1001);
+    }
+
+    #[test]
+    fn rows_must_have_the_same_number_of_values() {
+      let rows: &[&[&dyn UnifiedToSql]] = &[&[&"A-1", &2], &[&"B-7"]];
+      let lines = TableValuedParam::new("dbo.OrderLine", rows);
+      let err = expand_params(DbClientType::Mssql, "EXEC p @P1", &[&lines])
+        .err()
+        .unwrap();
+      assert_eq!(
+        err.to_string(),
+        "Row 2 of the `dbo.OrderLine` table has 1 values, expected 2"
+      );
+
+      let rows: &[&[&dyn UnifiedToSql]] = &[&[]];
+      let lines = TableValuedParam::new("dbo.OrderLine", rows);
+      assert!(expand_params(DbClientType::Mssql, "EXEC p @P1", &[&lines]).is_err());
+    }
+
+    #[test]
+    fn type_names_are_quoted() {
+      assert_eq!(quote_type_name("OrderLine").unwrap(), "[OrderLine]");
+      assert_eq!(
+        quote_type_name("dbo.Order_Line2").unwrap(),
+        "[dbo].[Order_Line2]"
+      );
+      assert_eq!(
+        quote_type_name("[my.schema].[Order ]]Line]").unwrap(),
+        "[my.schema].[Order ]]Line]"
+      );
+      for name in [
+        "",
+        "dbo.",
+        ".OrderLine",
+        "a.b.c",
+        "Order Line",
+        "1Line",
+        "dbo.OrderLine; DROP TABLE users --",
+        "[dbo].[OrderLine]; DROP TABLE users --",
+        "[dbo",
+        "[]",
+      ] {
+        assert!(quote_type_name(name).is_err(), "{}", name);
+      }
+    }
+
+    #[test]
+    fn invalid_type_names_fail_the_call() {
+      let lines = TableValuedParam::new("dbo.T AS TABLE (x INT); DROP TABLE users; --", &[]);
+      let err = expand_params(DbClientType::Mssql, "EXEC p @P1", &[&lines])
+        .err()
+        .unwrap();
+      assert!(
+        err.to_string().starts_with("Invalid table type name"),
+        "{}",
+        err
+      );
+    }
+  }
+}
@@ -0,0 +1,332 @@
+use anyhow::Result;
+use std::{
+  fmt,
+  sync::{
+    Arc,
+    atomic::{AtomicU64, AtomicUsize, Ordering},
+  },
+  time::{Duration, Instant},
+};
+
+use crate::error::DbError;
+use crate::pool_manager::{PoolHandle, PooledClient};
+
+/// How [`PoolGroup::read`] picks among the healthy replicas.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReplicaSelection {
+  /// Each read goes to the next replica in turn
+  #[default]
+  RoundRobin,
+  /// Each read goes to the replica with the fewest connections checked out
+  LeastBusy,
+}
+
+/// Routing behaviour of a [`PoolGroup`].
+#[derive(Debug, Clone)]
+pub struct PoolGroupOptions {
+  pub selection: ReplicaSelection,
+  /// How long a replica whose connection failed or broke is skipped, 5 seconds by default.
+  pub unhealthy_cooldown: Duration,
+  /// Read from the primary when no replica can serve the read. On by default.
+  pub fallback_to_primary: bool,
+}
+
+impl Default for PoolGroupOptions {
+  fn default() -> Self {
+    Self {
+      selection: ReplicaSelection::default(),
+      unhealthy_cooldown: Duration::from_secs(5),
+      fallback_to_primary: true,
+    }
+  }
+}
+
+impl PoolGroupOptions {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn selection(mut self, selection: ReplicaSelection) -> Self {
+    self.selection = selection;
+    self
+  }
+
+  pub fn unhealthy_cooldown(mut self, cooldown: Duration) -> Self {
+    self.unhealthy_cooldown = cooldown;
+    self
+  }
+
+  pub fn fallback_to_primary(mut self, fallback_to_primary: bool) -> Self {
+    self.fallback_to_primary = fallback_to_primary;
+    self
+  }
+}
+
+/// A primary pool and its read replicas, created with
+/// [`DbManager::init_group`](crate::pool_manager::DbManager::init_group). Writes and
+/// transactions use [`PoolGroup::write`], queries that tolerate replication lag use
+/// [`PoolGroup::read`]. Cheap to clone.
+#[derive(Clone)]
+pub struct PoolGroup {
+  inner: Arc<GroupInner>,
+}
+
+struct GroupInner {
+  name: String,
+  primary: PoolHandle,
+  replicas: Vec<Replica>,
+  options: PoolGroupOptions,
+  /// Round-robin position
+  next: AtomicUsize,
+  /// Reference point of the replicas' `unhealthy_until`
+  created: Instant,
+}
+
+struct Replica {
+  pool: PoolHandle,
+  /// Nanoseconds after `created` until which the replica is skipped, 0 when healthy
+  unhealthy_until: AtomicU64,
+}
+
+impl PoolGroup {
+  pub(crate) fn new(
+    name: &str,
+    primary: PoolHandle,
+    replicas: Vec<PoolHandle>,
+    options: PoolGroupOptions,
+  ) -> Self {
+    Self {
+      inner: Arc::new(GroupInner {
+        name: name.to_string(),
+        primary,
+        replicas: replicas
+          .into_iter()
+          .map(|pool| Replica {
+            pool,
+            unhealthy_until: AtomicU64::new(0),
+          })
+          .collect(),
+        options,
+        next: AtomicUsize::new(0),
+        created: Instant::now(),
+      }),
+    }
+  }
+
+  pub fn name(&self) -> &str {
+    &self.inner.name
+  }
+
+  pub fn primary(&self) -> &PoolHandle {
+    &self.inner.primary
+  }
+
+  pub fn replicas(&self) -> impl Iterator<Item = &PoolHandle> {
+    self.inner.replicas.iter().map(|replica| &replica.pool)
+  }
+
+  /// Connection to the primary, for writes and transactions
+  pub async fn write(&self) -> Result<PooledClient> {
+    self.inner.primary.get_client().await
+  }
+
+  /// Connection to a healthy replica, picked according to [`PoolGroupOptions::selection`].
+  ///
+  /// A replica failing to hand out a connection is skipped for
+  /// [`PoolGroupOptions::unhealthy_cooldown`] and the next one is tried; one that is merely
+  /// exhausted is tried again on the next read. A replica is also skipped once a
+  /// [`SqlRepo`](crate::SqlRepo) call on a connection it handed out fails with a broken
+  /// connection, and a retry of that call runs on a connection picked here again. Without any
+  /// usable replica the read goes to the primary, unless
+  /// [`PoolGroupOptions::fallback_to_primary`] is off.
+  pub async fn read(&self) -> Result<PooledClient> {
+    let inner = &self.inner;
+    let mut last_error = None;
+    for index in self.candidates() {
+      let replica = &inner.replicas[index];
+      match replica.pool.get_client().await {
+        Ok(mut client) => {
+          client.replica = Some(ReplicaHealth {
+            group: inner.clone(),
+            index,
+          });
+          return Ok(client);
+        }
+        Err(e) => {
+          if !matches!(e.downcast_ref::<DbError>(), Some(DbError::PoolExhausted(_))) {
+            inner.mark_unhealthy(index);
+          }
+          last_error = Some(e);
+        }
+      }
+    }
+    if inner.options.fallback_to_primary || inner.replicas.is_empty() {
+      return self.write().await;
+    }
+    Err(
+      last_error
+        .unwrap_or_else(|| anyhow::anyhow!("No healthy replica in pool group `{}`", inner.name)),
+    )
+  }
+
+  /// Open replicas not in their cooldown, in the order they should be tried
+  fn candidates(&self) -> Vec<usize> {
+    let inner = &self.inner;
+    let count = inner.replicas.len();
+    if count == 0 {
+      return Vec::new();
+    }
+    let now = inner.now();
+    let start = inner.next.fetch_add(1, Ordering::Relaxed) % count;
+    let mut candidates: Vec<usize> = (start..count)
+      .chain(0..start)
+      .filter(|&index| {
+        let replica = &inner.replicas[index];
+        !replica.pool.is_closed() && replica.unhealthy_until.load(Ordering::Relaxed) <= now
+      })
+      .collect();
+    if inner.options.selection == ReplicaSelection::LeastBusy {
+      // Stable, so ties keep the round-robin order
+      candidates.sort_by_key(|&index| inner.replicas[index].pool.stats().in_use);
+    }
+    candidates
+  }
+}
+
+impl GroupInner {
+  fn mark_unhealthy(&self, index: usize) {
+    let replica = &self.replicas[index];
+    let until = self.now() + self.options.unhealthy_cooldown.as_nanos() as u64;
+    replica.unhealthy_until.store(until, Ordering::Relaxed);
+    #[cfg(feature = "tracing")]
+    tracing::warn!(
+      group = %self.name,
+      replica = replica.pool.name(),
+      "replica unhealthy, skipped for {:?}",
+      self.options.unhealthy_cooldown
+    );
+  }
+
+  fn now(&self) -> u64 {
+    self.created.elapsed().as_nanos() as u64
+  }
+}
+
+/// The replica a connection returned by [`PoolGroup::read`] came from
+pub(crate) struct ReplicaHealth {
+  group: Arc<GroupInner>,
+  index: usize,
+}
+
+impl ReplicaHealth {
+  /// Skip the replica for the cooldown after its connection broke
+  pub(crate) fn connection_broken(&self) {
+    self.group.mark_unhealthy(self.index);
+  }
+
+  /// Connection for another attempt of a read, from a healthy replica or the primary
+  pub(crate) async fn reroute(&self) -> Result<PooledClient> {
+    PoolGroup {
+      inner: self.group.clone(),
+    }
+    .read()
+    .await
+  }
+}
+
+impl fmt::Debug for PoolGroup {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("PoolGroup")
+      .field("name", &self.inner.name)
+      .field("primary", &self.inner.primary.name())
+      .field(
+        "replicas",
+        &self.replicas().map(PoolHandle::name).collect::<Vec<_>>(),
+      )
+      .field("options", &self.inner.options)
+      .finish()
+  }
+}
+
+#[cfg(all(test, any(feature = "mssql", feature = "pgsql")))]
+mod tests {
+  use super::*;
+  use crate::pool_manager::{
+    DbManager,
+    tests::{discarded_client, manager_with_pools},
+  };
+
+  async fn group(options: PoolGroupOptions) -> (DbManager, PoolGroup) {
+    let manager = manager_with_pools(&["primary", "a", "b", "c"]).await;
+    let group = manager
+      .init_group("orders", "primary", &["a", "b", "c"], options)
+      .unwrap();
+    (manager, group)
+  }
+
+  #[tokio::test]
+  async fn round_robin_rotates_the_starting_replica() {
+    let (_manager, group) = group(PoolGroupOptions::new()).await;
+    assert_eq!(group.candidates(), [0, 1, 2]);
+    assert_eq!(group.candidates(), [1, 2, 0]);
+    assert_eq!(group.candidates(), [2, 0, 1]);
+    assert_eq!(group.candidates(), [0, 1, 2]);
+  }
+
+  #[tokio::test]
+  async fn unhealthy_replicas_are_skipped_until_the_cooldown_ends() {
+    let options = PoolGroupOptions::new().unhealthy_cooldown(Duration::from_millis(50));
+    let (_manager, group) = group(options).await;
+    group.inner.mark_unhealthy(1);
+    assert_eq!(group.candidates(), [0, 2]);
+    assert_eq!(group.candidates(), [2, 0]);
+    assert_eq!(group.candidates(), [2, 0]);
+
+    tokio::time::sleep(Duration::from_millis(60)).await;
+    assert_eq!(group.candidates(), [0, 1, 2]);
+  }
+
+  #[tokio::test]
+  async fn failed_checkouts_mark_replicas_unhealthy() {
+    let options = PoolGroupOptions::new().fallback_to_primary(false);
+    let (manager, group) = group(options).await;
+    assert!(group.read().await.is_err());
+    assert!(group.candidates().is_empty());
+
+    let err = group.read().await.err().unwrap();
+    assert!(err.to_string().contains("No healthy replica"), "{}", err);
+    for name in ["a", "b", "c"] {
+      assert_eq!(manager.pool(name).unwrap().stats().errors, 1);
+    }
+  }
+
+  #[tokio::test]
+  async fn broken_connections_mark_their_replica_unhealthy() {
+    let (_manager, group) = group(PoolGroupOptions::new()).await;
+    let health = ReplicaHealth {
+      group: group.inner.clone(),
+      index: 2,
+    };
+    health.connection_broken();
+    assert_eq!(group.candidates(), [0, 1]);
+  }
+
+  #[tokio::test]
+  async fn broken_connections_are_replaced_from_another_replica_or_the_primary() {
+    let (manager, group) = group(PoolGroupOptions::new()).await;
+    let health = ReplicaHealth {
+      group: group.inner.clone(),
+      index: 0,
+    };
+    health.connection_broken();
+    let mut client = discarded_client(&manager, "a", Some(health));
+    // Every pool is unreachable, so the replacement fails after trying all but the broken one
+    assert!(client.replace_connection().await.is_err());
+    assert_eq!(manager.pool("a").unwrap().stats().errors, 0);
+    for name in ["b", "c", "primary"] {
+      assert_eq!(manager.pool(name).unwrap().stats().errors, 1, "{}", name);
+    }
+    drop(client);
+    assert_eq!(manager.pool("a").unwrap().stats().in_use, 0);
+  }
+}
@@ -1,33 +1,121 @@
 use anyhow::Result;
-use std::{collections::HashMap, sync::Arc};
-use tokio::sync::Mutex;
+use std::{
+  collections::HashMap,
+  fmt,
+  future::Future,
+  sync::{
+    Arc, MutexGuard, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard,
+    atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
+  },
+  time::{Duration, Instant},
+};
+use tokio::sync::Notify;
+
+use crate::config::{Backend, PoolConfig};
+use crate::error::DbError;
+use crate::instrument::OpSpan;
+use crate::pool_group::{PoolGroup, PoolGroupOptions, ReplicaHealth};
+use crate::retry::RetryPolicy;
+use crate::stats::{PoolCounters, PoolStats};
 
 #[cfg(feature = "mssql")]
 mod mssql_ops {
-  pub use crate::types::sql::mssql::{Client, Config, FromSql, Row as MssqlRow};
+  pub use crate::types::sql::mssql::{Client, Config, FromSql, Row as MssqlRow, SqlBrowser};
   pub use tokio::net::TcpStream;
   pub use tokio_util::compat::{Compat, TokioAsyncWriteCompatExt};
+  pub type MssqlClient = Client<Compat<TcpStream>>;
   /// Helper function to wrap a TcpStream for Tiberius
   pub fn compat_stream(stream: TcpStream) -> Compat<TcpStream> {
     stream.compat_write()
   }
+
+  /// Connect to every address `addr` resolves to at once, keeping the first to answer
+  pub async fn connect_any(addr: &str) -> anyhow::Result<TcpStream> {
+    let mut attempts = tokio::task::JoinSet::new();
+    for addr in tokio::net::lookup_host(addr).await? {
+      attempts.spawn(TcpStream::connect(addr));
+    }
+    let mut last_error = None;
+    while let Some(attempt) = attempts.join_next().await {
+      match attempt? {
+        Ok(tcp) => return Ok(tcp),
+        Err(e) => last_error = Some(e),
+      }
+    }
+    Err(match last_error {
+      Some(e) => e.into(),
+      None => anyhow::anyhow!("`{}` did not resolve to any address", addr),
+    })
+  }
+
+  /// Whether the connected database accepts writes, i.e. this host is the primary replica
+  pub async fn is_writable(client: &mut MssqlClient) -> anyhow::Result<bool> {
+    let row = client
+      .simple_query("SELECT CAST(DATABASEPROPERTYEX(DB_NAME(), 'Updateability') AS nvarchar(16))")
+      .await?
+      .into_row()
+      .await?;
+    Ok(row.is_some_and(|row| row.get::<&str, _>(0) == Some("READ_WRITE")))
+  }
 }
 
 #[cfg(feature = "pgsql")]
 mod pgsql_ops {
   pub use crate::types::sql::pgsql::{
-    Client as PgClient, NoTls, Row as PgRow, connect, types::FromSql,
+    CancelToken, Client as PgClient, Row as PgRow, types::FromSql,
   };
 }
 
 // The DbClient type will be an enum that is only compiled if the corresponding feature is enabled.
 pub enum DbClient {
   #[cfg(feature = "mssql")]
-  Mssql(mssql_ops::Client<mssql_ops::Compat<mssql_ops::TcpStream>>),
+  Mssql(mssql_ops::MssqlClient),
   #[cfg(feature = "pgsql")]
   Pgsql(pgsql_ops::PgClient),
 }
 
+impl DbClient {
+  pub fn client_type(&self) -> DbClientType {
+    match self {
+      #[cfg(feature = "mssql")]
+      DbClient::Mssql(_) => DbClientType::Mssql,
+      #[cfg(feature = "pgsql")]
+      DbClient::Pgsql(_) => DbClientType::Pgsql,
+    }
+  }
+
+  /// Run one or more statements separated by semicolons, without parameters or results
+  pub async fn batch_execute(&mut self, sql: &str) -> Result<()> {
+    match self {
+      #[cfg(feature = "mssql")]
+      DbClient::Mssql(c) => {
+        c.simple_query(sql).await?.into_results().await?;
+        Ok(())
+      }
+      #[cfg(feature = "pgsql")]
+      DbClient::Pgsql(c) => Ok(c.batch_execute(sql).await?),
+    }
+  }
+
+  /// End the session, logging out of the server instead of just dropping the socket
+  async fn close(self) {
+    match self {
+      #[cfg(feature = "mssql")]
+      DbClient::Mssql(client) =>
+      {
+        #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+        if let Err(e) = client.close().await {
+          #[cfg(feature = "tracing")]
+          tracing::warn!(error = %e, "failed to close MSSQL connection");
+        }
+      }
+      // Dropping the client ends its connection task, which sends the terminate message.
+      #[cfg(feature = "pgsql")]
+      DbClient::Pgsql(client) => drop(client),
+    }
+  }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub enum DbClientType {
   #[cfg(feature = "mssql")]
@@ -36,136 +124,711 @@ pub enum DbClientType {
   Pgsql,
 }
 
-pub type DbPool = Arc<Mutex<Vec<DbClient>>>;
+impl DbClientType {
+  /// Name of the database system, as used in log fields
+  pub fn system_name(&self) -> &'static str {
+    match *self {
+      #[cfg(feature = "mssql")]
+      DbClientType::Mssql => "mssql",
+      #[cfg(feature = "pgsql")]
+      DbClientType::Pgsql => "postgresql",
+    }
+  }
+}
+
+/// How long [`DbManager::close_pool`] and [`DbManager::close_all`] wait for checked-out
+/// connections to come back.
+pub const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Per-pool behaviour applied to [`SqlRepo`](crate::SqlRepo) calls made on its clients.
+#[derive(Debug, Clone, Default)]
+pub struct PoolOptions {
+  pub retry_policy: RetryPolicy,
+  /// Default bound for every command run on the pool, see [`PooledClient::with_command_timeout`]
+  pub command_timeout: Option<Duration>,
+  /// Record parameter values in traces. Off by default, values are redacted.
+  pub log_parameters: bool,
+}
+
+impl PoolOptions {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+    self.retry_policy = retry_policy;
+    self
+  }
+
+  pub fn command_timeout(mut self, timeout: Duration) -> Self {
+    self.command_timeout = Some(timeout);
+    self
+  }
+
+  pub fn log_parameters(mut self, log_parameters: bool) -> Self {
+    self.log_parameters = log_parameters;
+    self
+  }
+}
+
+pub struct DbPool {
+  pub(crate) config: PoolConfig,
+  /// Connections currently open, idle or checked out
+  open: AtomicU32,
+  /// Idle connections. A plain mutex, never held across an await, so clients can be returned
+  /// from `Drop` without a runtime.
+  idle: std::sync::Mutex<Vec<DbClient>>,
+  pub(crate) stats: PoolCounters,
+  /// Set once the pool is closing: no more checkouts, returned connections are closed
+  closed: AtomicBool,
+  /// Woken whenever a checked-out connection is released
+  released: Notify,
+  /// Bumped when the pool's connections may point at the wrong server after a failover.
+  /// Connections opened under an older generation are closed when released.
+  generation: AtomicU64,
+}
+
+impl DbPool {
+  /// Claim room for one more connection, if the pool is below its size
+  fn reserve_slot(&self) -> bool {
+    self
+      .open
+      .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |open| {
+        (open < self.config.max_size).then_some(open + 1)
+      })
+      .is_ok()
+  }
+
+  fn release_slot(&self) {
+    self.open.fetch_sub(1, Ordering::SeqCst);
+  }
+
+  fn snapshot(&self) -> PoolStats {
+    self
+      .stats
+      .snapshot(self.config.max_size, self.open.load(Ordering::SeqCst))
+  }
+
+  fn is_closed(&self) -> bool {
+    self.closed.load(Ordering::SeqCst)
+  }
+
+  fn idle(&self) -> MutexGuard<'_, Vec<DbClient>> {
+    // The list stays consistent even if a holder panicked
+    self.idle.lock().unwrap_or_else(PoisonError::into_inner)
+  }
+
+  fn generation(&self) -> u64 {
+    self.generation.load(Ordering::SeqCst)
+  }
+
+  /// Close the idle connections and retire the checked-out ones, so new connections look for
+  /// the writable host again
+  fn invalidate(self: &Arc<Self>) {
+    self.generation.fetch_add(1, Ordering::SeqCst);
+    let idle = std::mem::take(&mut *self.idle());
+    for client in idle {
+      self.discard(client);
+    }
+  }
+
+  /// Take back a released connection, through the `after_release` hook if there is one. The
+  /// connection counts as in use until the hook is done, so a drain waits for it.
+  fn return_client(self: &Arc<Self>, client: DbClient, generation: u64) {
+    let Some(hook) = self.config.hooks.after_release.clone() else {
+      self.put_back(client, generation);
+      self.checked_in();
+      return;
+    };
+    match tokio::runtime::Handle::try_current() {
+      Ok(runtime) => {
+        let mut releasing = Releasing {
+          pool: self.clone(),
+          client: Some(client),
+        };
+        runtime.spawn(async move {
+          let client = releasing
+            .client
+            .as_mut()
+            .expect("set until the hook is done");
+          let result = hook(client).await;
+          let client = releasing.client.take().expect("set until the hook is done");
+          match result {
+            Ok(()) => releasing.pool.put_back(client, generation),
+            #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+            Err(e) => {
+              #[cfg(feature = "tracing")]
+              tracing::warn!(error = %e, "connection rejected by after_release");
+              releasing.pool.close_client(client).await;
+            }
+          }
+        });
+      }
+      // The connection cannot be reset without a runtime, so it is not reused
+      Err(_) => {
+        #[cfg(feature = "tracing")]
+        tracing::warn!("connection released outside a Tokio runtime, closed without after_release");
+        drop(client);
+        self.connection_closed();
+        self.checked_in();
+      }
+    }
+  }
+
+  /// A checked-out connection is back or gone; wake a drain waiting for it
+  fn checked_in(&self) {
+    self.stats.released();
+    self.released.notify_waiters();
+  }
+
+  /// Put a released connection back, immediately visible to the next checkout, or close it if
+  /// the pool is closing or it predates a failover
+  fn put_back(self: &Arc<Self>, client: DbClient, generation: u64) {
+    let mut idle = self.idle();
+    // Checked under the lock, so a drain taking the idle connections cannot miss this one
+    if !self.is_closed() && generation == self.generation() {
+      idle.push(client);
+      return;
+    }
+    drop(idle);
+    self.discard(client);
+  }
+
+  /// Close a connection that leaves the pool, in the background if there is a runtime
+  fn discard(self: &Arc<Self>, client: DbClient) {
+    match tokio::runtime::Handle::try_current() {
+      Ok(runtime) => {
+        let pool = self.clone();
+        runtime.spawn(async move { pool.close_client(client).await });
+      }
+      // Outside a runtime (e.g. after shutdown) the session can only be dropped
+      Err(_) => {
+        drop(client);
+        self.connection_closed();
+      }
+    }
+  }
+
+  async fn close_client(&self, client: DbClient) {
+    client.close().await;
+    self.connection_closed();
+  }
+
+  fn connection_closed(&self) {
+    self.release_slot();
+    self.stats.connection_closed();
+  }
+
+  /// Stop handing out connections, wait up to `timeout` for checked-out ones and close the
+  /// idle ones. Connections still in use after the timeout are closed when released.
+  async fn drain(&self, pool_name: &str, timeout: Duration) -> Result<()> {
+    self.closed.store(true, Ordering::SeqCst);
+    let all_released = async {
+      loop {
+        let released = self.released.notified();
+        tokio::pin!(released);
+        released.as_mut().enable();
+        if self.stats.in_use() == 0 {
+          break;
+        }
+        released.await;
+      }
+    };
+    let drained = tokio::time::timeout(timeout, all_released).await.is_ok();
+
+    let idle = std::mem::take(&mut *self.idle());
+    for client in idle {
+      self.close_client(client).await;
+    }
+    if drained {
+      Ok(())
+    } else {
+      Err(anyhow::anyhow!(
+        "Pool `{}` closed with {} connection(s) still in use after {:?}",
+        pool_name,
+        self.stats.in_use(),
+        timeout
+      ))
+    }
+  }
+}
 
 pub struct DbManager {
-  pools: Arc<Mutex<HashMap<String, DbPool>>>,
+  /// Registry of pools by name. Only locked briefly to look up, add or remove a pool, never
+  /// across an await, so pools do not contend with each other.
+  pools: Arc<RwLock<HashMap<String, Arc<DbPool>>>>,
+  groups: Arc<RwLock<HashMap<String, PoolGroup>>>,
 }
 
 impl DbManager {
   pub fn new() -> Self {
     Self {
-      pools: Arc::new(Mutex::new(HashMap::new())),
+      pools: Arc::new(RwLock::new(HashMap::new())),
+      groups: Arc::new(RwLock::new(HashMap::new())),
     }
   }
 
+  fn registry(&self) -> RwLockReadGuard<'_, HashMap<String, Arc<DbPool>>> {
+    self.pools.read().unwrap_or_else(PoisonError::into_inner)
+  }
+
+  fn registry_mut(&self) -> RwLockWriteGuard<'_, HashMap<String, Arc<DbPool>>> {
+    self.pools.write().unwrap_or_else(PoisonError::into_inner)
+  }
+
+  fn lookup(&self, pool_name: &str) -> Result<Arc<DbPool>> {
+    self
+      .registry()
+      .get(pool_name)
+      .cloned()
+      .ok_or_else(|| anyhow::anyhow!("Pool `{}` not found", pool_name))
+  }
+
+  fn handle(&self, pool_name: &str, pool: Arc<DbPool>) -> PoolHandle {
+    PoolHandle {
+      name: pool_name.into(),
+      pool,
+      manager: self.clone(),
+    }
+  }
+
+  /// Handle of a pool initialized earlier, by name
+  pub fn pool(&self, pool_name: &str) -> Result<PoolHandle> {
+    Ok(self.handle(pool_name, self.lookup(pool_name)?))
+  }
+
+  /// Group initialized pools into one primary and its read replicas, replacing any group of
+  /// the same name
+  pub fn init_group(
+    &self,
+    group_name: &str,
+    primary: &str,
+    replicas: &[&str],
+    options: PoolGroupOptions,
+  ) -> Result<PoolGroup> {
+    let replicas = replicas
+      .iter()
+      .map(|replica| self.pool(replica))
+      .collect::<Result<_>>()?;
+    let group = PoolGroup::new(group_name, self.pool(primary)?, replicas, options);
+    self
+      .groups
+      .write()
+      .unwrap_or_else(PoisonError::into_inner)
+      .insert(group_name.to_string(), group.clone());
+    Ok(group)
+  }
+
+  /// Pool group initialized earlier, by name
+  pub fn group(&self, group_name: &str) -> Result<PoolGroup> {
+    self
+      .groups
+      .read()
+      .unwrap_or_else(PoisonError::into_inner)
+      .get(group_name)
+      .cloned()
+      .ok_or_else(|| anyhow::anyhow!("Pool group `{}` not found", group_name))
+  }
+
   /// Initialize a connection pool for a given name (if not exists)
-  pub async fn init_pool(&self, pool_name: &str, conn_str: &str, pool_size: u32) -> Result<()> {
-    let mut pools = self.pools.lock().await;
-    if pools.contains_key(pool_name) {
-      return Ok(());
+  pub async fn init_pool(
+    &self,
+    pool_name: &str,
+    conn_str: &str,
+    pool_size: u32,
+  ) -> Result<PoolHandle> {
+    self
+      .init_pool_with_options(pool_name, conn_str, pool_size, PoolOptions::default())
+      .await
+  }
+
+  /// Initialize a connection pool with explicit [`PoolOptions`] (if not exists)
+  pub async fn init_pool_with_options(
+    &self,
+    pool_name: &str,
+    conn_str: &str,
+    pool_size: u32,
+    options: PoolOptions,
+  ) -> Result<PoolHandle> {
+    let config = PoolConfig::parse(conn_str)?
+      .max_size(pool_size)
+      .options(options);
+    self.init_pool_with_config(pool_name, config).await
+  }
+
+  /// Initialize a connection pool from a typed [`PoolConfig`] (if not exists), returning a
+  /// handle to it (or to the existing pool of that name)
+  ///
+  /// Connections are opened without holding the registry, so other pools stay usable meanwhile.
+  pub async fn init_pool_with_config(
+    &self,
+    pool_name: &str,
+    config: PoolConfig,
+  ) -> Result<PoolHandle> {
+    config.validate()?;
+    if let Ok(existing) = self.pool(pool_name) {
+      return Ok(existing);
+    }
+
+    let stats = PoolCounters::new(pool_name);
+    let initial_size = config.initial_size();
+    let mut connections = Vec::with_capacity(initial_size as usize);
+    for _ in 0..initial_size {
+      connections.push(DbManager::connect(&config).await?);
+      stats.connection_created();
+    }
+
+    let pool = Arc::new(DbPool {
+      config,
+      open: AtomicU32::new(initial_size),
+      idle: std::sync::Mutex::new(connections),
+      stats,
+      closed: AtomicBool::new(false),
+      released: Notify::new(),
+      generation: AtomicU64::new(0),
+    });
+    let existing = {
+      let mut pools = self.registry_mut();
+      match pools.get(pool_name) {
+        Some(existing) => Some(existing.clone()),
+        None => {
+          pools.insert(pool_name.to_string(), pool.clone());
+          None
+        }
+      }
+    };
+    match existing {
+      // Another caller initialized the same pool first, keep theirs
+      Some(existing) => {
+        pool.drain(pool_name, Duration::ZERO).await?;
+        Ok(self.handle(pool_name, existing))
+      }
+      None => Ok(self.handle(pool_name, pool)),
     }
+  }
 
-    let mut connections = Vec::with_capacity(pool_size as usize);
+  /// Create a manager with the pools declared in a TOML or YAML file, see
+  /// [`ManagerConfig`](crate::config::ManagerConfig)
+  #[cfg(feature = "config-file")]
+  pub async fn from_config(path: impl AsRef<std::path::Path>) -> Result<Self> {
+    let manager = Self::new();
+    manager
+      .init_pools(crate::config::ManagerConfig::load(path)?)
+      .await?;
+    Ok(manager)
+  }
 
-    if conn_str.starts_with("postgresql://") || conn_str.starts_with("postgres://") {
+  /// Initialize every pool of a [`ManagerConfig`](crate::config::ManagerConfig)
+  #[cfg(feature = "config-file")]
+  pub async fn init_pools(&self, config: crate::config::ManagerConfig) -> Result<()> {
+    for (pool_name, pool_config) in config.pools {
+      self
+        .init_pool_with_config(&pool_name, pool_config)
+        .await
+        .map_err(|e| anyhow::anyhow!("Pool `{}`: {}", pool_name, e))?;
+    }
+    Ok(())
+  }
+
+  /// Open a single connection to the pool's database and run its `after_connect` hook
+  async fn connect(config: &PoolConfig) -> Result<DbClient> {
+    let mut client = DbManager::open(config).await?;
+    let Some(hook) = &config.hooks.after_connect else {
+      return Ok(client);
+    };
+    match hook(&mut client).await {
+      Ok(()) => Ok(client),
+      Err(e) => {
+        client.close().await;
+        Err(anyhow::anyhow!(
+          "Connection rejected by after_connect: {}",
+          e
+        ))
+      }
+    }
+  }
+
+  async fn open(config: &PoolConfig) -> Result<DbClient> {
+    let config = config.resolve_credentials().await?;
+    let config = config.as_ref();
+    match config.backend {
       #[cfg(feature = "pgsql")]
-      for _ in 0..pool_size {
-        let (client, connection) = pgsql_ops::connect(
-          &DbManager::postgres_url_to_tokio(conn_str),
-          pgsql_ops::NoTls,
-        )
-        .await?;
+      Backend::Pgsql => {
+        let drivers = crate::config::pgsql::driver_configs(config)?;
+        let mut failures = Vec::new();
+        let mut last_error = None;
+        let mut connected = None;
+        for driver in drivers {
+          match driver.connect(config.pg_tls.connector()?).await {
+            Ok(pair) => {
+              connected = Some(pair);
+              break;
+            }
+            Err(e) => {
+              let hosts = crate::config::pgsql::host_list(&driver);
+              failures.push(format!("{}: {}", hosts, e));
+              last_error = Some(e);
+            }
+          }
+        }
+        let Some((client, connection)) = connected else {
+          let err = anyhow::Error::new(last_error.expect("at least one host is tried"));
+          return Err(if failures.len() > 1 {
+            err.context(format!(
+              "No PostgreSQL host accepted the connection ({})",
+              failures.join("; ")
+            ))
+          } else {
+            err
+          });
+        };
         tokio::spawn(async move {
+          #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
           if let Err(e) = connection.await {
-            eprintln!("connection error: {}", e);
+            #[cfg(feature = "tracing")]
+            tracing::error!(error = %e, "PostgreSQL connection closed with an error");
           }
         });
-        connections.push(DbClient::Pgsql(client));
+        Ok(DbClient::Pgsql(client))
       }
-    } else {
       #[cfg(feature = "mssql")]
-      if let Some(config) = mssql_ops::Config::from_ado_string(conn_str)
-        .ok()
-        .or_else(|| mssql_ops::Config::from_jdbc_string(conn_str).ok())
-      {
-        for _ in 0..pool_size {
-          let mut config = config.clone();
-          config.trust_cert();
-          let tcp = mssql_ops::TcpStream::connect(config.get_addr()).await?;
-          tcp.set_nodelay(true)?;
-          let client = mssql_ops::Client::connect(config, mssql_ops::compat_stream(tcp)).await?;
-          connections.push(DbClient::Mssql(client));
+      Backend::Mssql => {
+        let drivers = crate::config::mssql::driver_configs(config)?;
+        // Only worth checking when another host could take the writes
+        let require_writable = drivers.len() > 1 && !config.read_only;
+        let mut failures = Vec::new();
+        let mut last_error = None;
+        for (driver, browse) in drivers {
+          let addr = driver.get_addr();
+          match DbManager::open_mssql(config, driver, browse, require_writable).await {
+            Ok(client) => return Ok(DbClient::Mssql(client)),
+            Err(e) => {
+              failures.push(format!("{}: {}", addr, e));
+              last_error = Some(e);
+            }
+          }
         }
+        let err = last_error.expect("at least one host is tried");
+        Err(if failures.len() > 1 {
+          err.context(format!(
+            "No MSSQL host accepted the connection ({})",
+            failures.join("; ")
+          ))
+        } else {
+          err
+        })
       }
+      #[allow(unreachable_patterns)]
+      backend => Err(anyhow::anyhow!("{} support is not enabled", backend)),
     }
+  }
 
-    pools.insert(pool_name.to_string(), Arc::new(Mutex::new(connections)));
-    Ok(())
+  /// Connect to one MSSQL host, rejecting it if writes are expected and its database is a
+  /// read-only replica. With `browse`, the port of the named instance is first asked to the
+  /// SQL Server Browser listening on the driver's port.
+  #[cfg(feature = "mssql")]
+  async fn open_mssql(
+    config: &PoolConfig,
+    driver: mssql_ops::Config,
+    browse: bool,
+    require_writable: bool,
+  ) -> Result<mssql_ops::MssqlClient> {
+    let connect = async {
+      let tcp = if browse {
+        <mssql_ops::TcpStream as mssql_ops::SqlBrowser>::connect_named(&driver).await?
+      } else if config.multi_subnet_failover {
+        mssql_ops::connect_any(&driver.get_addr()).await?
+      } else {
+        mssql_ops::TcpStream::connect(driver.get_addr()).await?
+      };
+      tcp.set_nodelay(true)?;
+      let mut client = mssql_ops::Client::connect(driver, mssql_ops::compat_stream(tcp)).await?;
+      if require_writable && !mssql_ops::is_writable(&mut client).await? {
+        let _ = client.close().await;
+        return Err(anyhow::anyhow!("Database is read-only on this host"));
+      }
+      Ok(client)
+    };
+    match config.connect_timeout {
+      Some(timeout) => tokio::time::timeout(timeout, connect)
+        .await
+        .map_err(|_| anyhow::anyhow!("Connecting timed out after {:?}", timeout))?,
+      None => connect.await,
+    }
   }
 
   /// Get a pooled client wrapped in a guard (auto-return when dropped)
   pub async fn get_client(&self, pool_name: &str) -> Result<PooledClient> {
-    let pools = self.pools.lock().await;
-    let pool = pools
-      .get(pool_name)
-      .ok_or_else(|| anyhow::anyhow!("Pool `{}` not found", pool_name))?;
-    let mut pool_guard = pool.lock().await;
-    let client = pool_guard
-      .pop()
-      .ok_or_else(|| anyhow::anyhow!("Pool `{}` is empty", pool_name))?;
-    Ok(PooledClient {
-      name: pool_name.to_string(),
-      client: Some(client),
-      manager: self.clone(),
-    })
+    OpSpan::acquire(pool_name)
+      .run(async { self.pool(pool_name)?.acquire().await }, |_| None)
+      .await
   }
 
-  /// Return a client back to the pool
-  async fn return_client(&self, pool_name: &str, client: DbClient) -> Result<()> {
-    let pools = self.pools.lock().await;
-    let pool = pools
-      .get(pool_name)
+  async fn checkout(pool: &DbPool, pool_name: &str) -> Result<DbClient> {
+    if pool.is_closed() {
+      return Err(anyhow::anyhow!("Pool `{}` is closed", pool_name));
+    }
+    let idle = loop {
+      let Some(mut client) = pool.idle().pop() else {
+        break None;
+      };
+      let Some(hook) = &pool.config.hooks.before_acquire else {
+        break Some(client);
+      };
+      match hook(&mut client).await {
+        Ok(()) => break Some(client),
+        // Rejected: close it and try the next idle connection
+        #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+        Err(e) => {
+          #[cfg(feature = "tracing")]
+          tracing::warn!(error = %e, "connection rejected by before_acquire");
+          pool.close_client(client).await;
+        }
+      }
+    };
+    match idle {
+      Some(client) => Ok(client),
+      // A connection was discarded earlier (e.g. after a timeout), open its replacement
+      None if pool.reserve_slot() => match DbManager::connect(&pool.config).await {
+        Ok(client) => {
+          pool.stats.connection_created();
+          Ok(client)
+        }
+        Err(e) => {
+          pool.release_slot();
+          Err(e)
+        }
+      },
+      None => Err(anyhow::Error::new(DbError::PoolExhausted(
+        pool_name.to_string(),
+      ))),
+    }
+  }
+
+  /// Snapshot the sizes, counters and acquire latency of a pool
+  pub fn stats(&self, pool_name: &str) -> Result<PoolStats> {
+    Ok(self.lookup(pool_name)?.snapshot())
+  }
+
+  /// Remove a pool and drain it, waiting up to [`DEFAULT_DRAIN_TIMEOUT`] for checked-out
+  /// connections to be released
+  pub async fn close_pool(&self, pool_name: &str) -> Result<()> {
+    self
+      .close_pool_with_timeout(pool_name, DEFAULT_DRAIN_TIMEOUT)
+      .await
+  }
+
+  /// Remove a pool so no new connections are handed out, wait up to `timeout` for checked-out
+  /// ones to be released, then close every session. Fails if connections were still in use
+  /// when the timeout elapsed; those are closed as soon as they are released.
+  ///
+  /// Groups whose primary is the pool are removed as well; groups using it as a replica skip it
+  /// from now on.
+  pub async fn close_pool_with_timeout(&self, pool_name: &str, timeout: Duration) -> Result<()> {
+    let pool = self
+      .registry_mut()
+      .remove(pool_name)
       .ok_or_else(|| anyhow::anyhow!("Pool `{}` not found", pool_name))?;
-    let mut pool_guard = pool.lock().await;
-    pool_guard.push(client);
-    Ok(())
+    self
+      .groups
+      .write()
+      .unwrap_or_else(PoisonError::into_inner)
+      .retain(|_, group| !Arc::ptr_eq(&group.primary().pool, &pool));
+    pool.drain(pool_name, timeout).await
   }
 
-  fn postgres_url_to_tokio(conn_url: &str) -> String {
-    // Parse the URL
-    // Remove the prefix
-    let conn_str = conn_url
-      .strip_prefix("postgresql://")
-      .expect("Invalid connection string");
+  /// Close every pool, see [`DbManager::close_pool`]
+  pub async fn close_all(&self) -> Result<()> {
+    self.close_all_with_timeout(DEFAULT_DRAIN_TIMEOUT).await
+  }
 
-    // Split by '@' to separate userinfo and hostinfo
-    let parts: Vec<&str> = conn_str.split('@').collect();
-    if parts.len() != 2 {
-      panic!("Invalid connection string format");
+  /// Close every pool, waiting up to `timeout` in total for checked-out connections
+  pub async fn close_all_with_timeout(&self, timeout: Duration) -> Result<()> {
+    self
+      .groups
+      .write()
+      .unwrap_or_else(PoisonError::into_inner)
+      .clear();
+    let pools: Vec<_> = self.registry_mut().drain().collect();
+    let deadline = tokio::time::Instant::now() + timeout;
+    let mut errors = Vec::new();
+    for (pool_name, pool) in pools {
+      let remaining = deadline.saturating_duration_since(tokio::time::Instant::now());
+      if let Err(e) = pool.drain(&pool_name, remaining).await {
+        errors.push(e.to_string());
+      }
     }
-
-    // Parse username and password
-    let user_pass: Vec<&str> = parts[0].split(':').collect();
-    if user_pass.len() != 2 {
-      panic!("Invalid username/password format");
+    if errors.is_empty() {
+      Ok(())
+    } else {
+      Err(anyhow::anyhow!("{}", errors.join("; ")))
     }
-    let username = user_pass[0];
-    let password = user_pass[1];
+  }
+}
 
-    // Parse host, port, and dbname
-    let host_port_db: Vec<&str> = parts[1].split('/').collect();
-    if host_port_db.len() != 2 {
-      panic!("Invalid host/db format");
-    }
+/// Cheap, cloneable reference to one pool, returned by [`DbManager::init_pool`] and
+/// [`DbManager::pool`]. Acquiring through it skips the lookup by name. Once the pool is closed,
+/// acquiring fails with "Pool `name` is closed".
+#[derive(Clone)]
+pub struct PoolHandle {
+  name: Arc<str>,
+  pool: Arc<DbPool>,
+  manager: DbManager,
+}
 
-    let host_port: Vec<&str> = host_port_db[0].split(':').collect();
-    if host_port.len() != 2 {
-      panic!("Invalid host/port format");
-    }
-    let host = host_port[0];
-    let port = host_port[1];
+impl PoolHandle {
+  pub fn name(&self) -> &str {
+    &self.name
+  }
+
+  /// Whether the pool was closed, see [`DbManager::close_pool`]
+  pub fn is_closed(&self) -> bool {
+    self.pool.is_closed()
+  }
 
-    // Extract dbname before '?' if query params exist
-    let dbname = host_port_db[1]
-      .split('?')
-      .next()
-      .expect("Invalid dbname format");
+  /// Get a pooled client wrapped in a guard (auto-return when dropped)
+  pub async fn get_client(&self) -> Result<PooledClient> {
+    OpSpan::acquire(&self.name)
+      .run(self.acquire(), |_| None)
+      .await
+  }
+
+  async fn acquire(&self) -> Result<PooledClient> {
+    let pool = &self.pool;
+    pool.stats.acquire_started();
+    let start = Instant::now();
+    let generation = pool.generation();
+    let client = DbManager::checkout(pool, &self.name).await;
+    pool.stats.acquire_finished(start.elapsed(), client.is_ok());
+    Ok(PooledClient {
+      name: self.name.to_string(),
+      client: Some(client?),
+      manager: self.manager.clone(),
+      pool: pool.clone(),
+      generation,
+      in_transaction: false,
+      command_timeout: None,
+      replica: None,
+    })
+  }
+
+  /// Snapshot the sizes, counters and acquire latency of the pool
+  pub fn stats(&self) -> PoolStats {
+    self.pool.snapshot()
+  }
+}
+
+impl fmt::Debug for PoolHandle {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("PoolHandle")
+      .field("name", &self.name)
+      .finish_non_exhaustive()
+  }
+}
 
-    format!(
-      "host={} user={} password={} port={} dbname={}",
-      host, username, password, port, dbname
-    )
+impl Default for DbManager {
+  fn default() -> Self {
+    Self::new()
   }
 }
 
@@ -173,6 +836,7 @@ impl Clone for DbManager {
   fn clone(&self) -> Self {
     Self {
       pools: self.pools.clone(),
+      groups: self.groups.clone(),
     }
   }
 }
@@ -182,25 +846,191 @@ pub struct PooledClient {
   pub name: String,
   pub client: Option<DbClient>,
   pub manager: DbManager,
+  pub(crate) pool: Arc<DbPool>,
+  /// Pool generation the connection was opened under
+  generation: u64,
+  pub(crate) in_transaction: bool,
+  command_timeout: Option<Duration>,
+  /// Set for connections handed out by [`PoolGroup::read`](crate::pool_group::PoolGroup::read)
+  pub(crate) replica: Option<ReplicaHealth>,
 }
 impl PooledClient {
-  pub fn client(&mut self) -> &mut DbClient {
-    self.client.as_mut().unwrap()
+  /// Fails if the connection was discarded after a timeout or failover and not reopened yet,
+  /// see [`PooledClient::ensure_connected`]
+  pub fn client(&mut self) -> Result<&mut DbClient> {
+    self.client.as_mut().ok_or_else(discarded)
+  }
+  pub fn client_ref(&self) -> Result<&DbClient> {
+    self.client.as_ref().ok_or_else(discarded)
+  }
+  /// Replace the held connection with a freshly opened one, dropping the old (broken) one
+  pub async fn reconnect(&mut self) -> Result<()> {
+    let generation = self.pool.generation();
+    let client = DbManager::connect(&self.pool.config).await?;
+    self.pool.stats.connection_created();
+    self.generation = generation;
+    if self.client.replace(client).is_some() {
+      self.pool.stats.connection_closed();
+    }
+    Ok(())
   }
-  pub fn client_ref(&self) -> &DbClient {
-    self.client.as_ref().unwrap()
+  /// Replace a broken connection before retrying. A connection from
+  /// [`PoolGroup::read`](crate::pool_group::PoolGroup::read) is replaced by one the group picks
+  /// again, so the retry moves off the replica that broke; others reconnect to their pool.
+  pub(crate) async fn replace_connection(&mut self) -> Result<()> {
+    let Some(replica) = &self.replica else {
+      return self.reconnect().await;
+    };
+    let fresh = replica.reroute().await?;
+    if self.client.take().is_some() {
+      self.pool.stats.connection_closed();
+    }
+    // Dropping the old guard frees its slot in the replica's pool
+    *self = fresh;
+    Ok(())
+  }
+  /// React to a server that stopped accepting writes: with several hosts configured, drop the
+  /// pool's connections so they reconnect to whichever host is now writable. The held
+  /// connection is kept until the end of a running transaction.
+  pub(crate) fn retarget(&mut self) {
+    if self.pool.config.hosts.len() < 2 {
+      return;
+    }
+    #[cfg(feature = "tracing")]
+    tracing::warn!(pool = %self.name, "server is read-only, reconnecting after failover");
+    self.pool.invalidate();
+    if !self.in_transaction && self.client.take().is_some() {
+      self.pool.stats.connection_closed();
+    }
+  }
+  /// Reopen the connection if it was discarded after a timeout
+  pub async fn ensure_connected(&mut self) -> Result<()> {
+    if self.client.is_none() {
+      self.reconnect().await?;
+    }
+    Ok(())
+  }
+  /// Bound the next [`SqlRepo`](crate::SqlRepo) call made with this client (including its
+  /// retries), overriding the pool's default command timeout for that call only
+  pub fn with_command_timeout(&mut self, timeout: Duration) -> &mut Self {
+    self.command_timeout = Some(timeout);
+    self
+  }
+  /// Timeout for the call about to start, consuming a per-call override
+  pub(crate) fn take_command_timeout(&mut self) -> Option<Duration> {
+    self
+      .command_timeout
+      .take()
+      .or(self.pool.config.options.command_timeout)
+  }
+  /// Reopen a discarded connection and capture what is needed to cancel the next command
+  pub(crate) async fn prepare_attempt(&mut self) -> Result<CancelHandle> {
+    if self.in_transaction && self.client.is_none() {
+      return Err(anyhow::anyhow!(
+        "Connection was discarded in the middle of a transaction"
+      ));
+    }
+    self.ensure_connected().await?;
+    Ok(match self.client_ref()? {
+      #[cfg(feature = "mssql")]
+      DbClient::Mssql(_) => CancelHandle::Discard,
+      #[cfg(feature = "pgsql")]
+      DbClient::Pgsql(c) => CancelHandle::Pgsql(c.cancel_token()),
+    })
+  }
+  /// Cancel the server-side work of a timed out command and discard its connection, so it is
+  /// never handed out again. Other results are passed through, as is a timeout whose connection
+  /// was already discarded, e.g. by a statement that timed out inside a transaction.
+  pub(crate) async fn finish_attempt<T>(
+    &mut self,
+    result: Result<T>,
+    cancel: CancelHandle,
+  ) -> Result<T> {
+    let Err(err) = result else {
+      return result;
+    };
+    #[cfg_attr(not(feature = "pgsql"), allow(unused_variables))]
+    let Some(DbError::CommandTimeout(timeout)) = err.downcast_ref::<DbError>() else {
+      return Err(err);
+    };
+    if self.client.is_none() {
+      return Err(err);
+    }
+    match cancel {
+      #[cfg(feature = "pgsql")]
+      CancelHandle::Pgsql(token) => {
+        // Best effort: the connection is dropped below either way.
+        if let Ok(tls) = self.pool.config.pg_tls.connector() {
+          let _ = tokio::time::timeout(*timeout, token.cancel_query(tls)).await;
+        }
+      }
+      // Closing the session makes the server abort the running batch.
+      CancelHandle::Discard => {}
+    }
+    #[cfg(feature = "tracing")]
+    tracing::warn!(?timeout, "command timed out, connection discarded");
+    self.pool.stats.timed_out();
+    self.pool.stats.connection_closed();
+    self.client = None;
+    Err(err)
+  }
+}
+
+/// How to stop a command that outlived its timeout
+pub(crate) enum CancelHandle {
+  /// Only closing the connection stops the command
+  #[cfg_attr(not(feature = "mssql"), allow(dead_code))]
+  Discard,
+  #[cfg(feature = "pgsql")]
+  Pgsql(pgsql_ops::CancelToken),
+}
+
+/// Await `fut`, failing with [`DbError::CommandTimeout`] if it does not finish within `timeout`
+pub(crate) async fn with_timeout<T>(
+  timeout: Option<Duration>,
+  fut: impl Future<Output = Result<T>>,
+) -> Result<T> {
+  match timeout {
+    Some(timeout) => tokio::time::timeout(timeout, fut)
+      .await
+      .map_err(|_| anyhow::Error::new(DbError::CommandTimeout(timeout)))?,
+    None => fut.await,
   }
 }
+
+fn discarded() -> anyhow::Error {
+  anyhow::anyhow!(
+    "Connection was discarded after a command timeout or failover; call `ensure_connected` to reopen it"
+  )
+}
+
 impl Drop for PooledClient {
+  fn drop(&mut self) {
+    match self.client.take() {
+      Some(client) => self.pool.return_client(client, self.generation),
+      // The connection was discarded, let the pool open a replacement on demand
+      None => {
+        self.pool.release_slot();
+        self.pool.checked_in();
+      }
+    }
+  }
+}
+
+/// A released connection going through `after_release`. Checked in once dropped, which also
+/// happens if the runtime shuts down before the hook is done; the connection is closed then.
+struct Releasing {
+  pool: Arc<DbPool>,
+  client: Option<DbClient>,
+}
+
+impl Drop for Releasing {
   fn drop(&mut self) {
     if let Some(client) = self.client.take() {
-      let name = self.name.clone();
-      let manager = self.manager.clone();
-      // Return client asynchronously in background
-      tokio::spawn(async move {
-        let _ = manager.return_client(&name, client).await;
-      });
+      drop(client);
+      self.pool.connection_closed();
     }
+    self.pool.checked_in();
   }
 }
 
@@ -226,6 +1056,7 @@ impl<'a> DbRow<'a> {
         }
         Err(anyhow::anyhow!("Failed to get {}", idx))
       }
+      #[allow(unreachable_patterns)]
       _ => Err(anyhow::anyhow!("Mismatched database driver")),
     }
   }
@@ -236,11 +1067,158 @@ impl<'a> DbRow<'a> {
     T: pgsql_ops::FromSql<'p>,
   {
     match self {
-      DbRow::Pgsql(row) => {
-        let value = row.try_get::<&str, T>(idx).map_err(|e| anyhow::anyhow!(e));
-        value
-      }
+      DbRow::Pgsql(row) => row.try_get::<&str, T>(idx).map_err(|e| anyhow::anyhow!(e)),
+      #[allow(unreachable_patterns)]
       _ => Err(anyhow::anyhow!("Mismatched database driver")),
     }
   }
 }
+
+#[cfg(all(test, any(feature = "mssql", feature = "pgsql")))]
+pub(crate) mod tests {
+  use super::*;
+  use crate::config::Backend;
+
+  /// A pool that opens no connection up front and whose server refuses connections
+  fn unreachable_config() -> PoolConfig {
+    #[cfg(feature = "pgsql")]
+    let backend = Backend::Pgsql;
+    #[cfg(not(feature = "pgsql"))]
+    let backend = Backend::Mssql;
+    PoolConfig::new(backend)
+      .host("127.0.0.1")
+      .port(1)
+      .min_size(0)
+      .connect_timeout(Duration::from_secs(1))
+  }
+
+  pub(crate) async fn manager_with_pools(names: &[&str]) -> DbManager {
+    let manager = DbManager::new();
+    for name in names {
+      manager
+        .init_pool_with_config(name, unreachable_config())
+        .await
+        .unwrap();
+    }
+    manager
+  }
+
+  /// Client counted as checked out of the pool whose connection was already discarded
+  pub(crate) fn discarded_client(
+    manager: &DbManager,
+    pool_name: &str,
+    replica: Option<ReplicaHealth>,
+  ) -> PooledClient {
+    let pool = manager.pool(pool_name).unwrap().pool;
+    pool.open.fetch_add(1, Ordering::SeqCst);
+    pool.stats.acquire_started();
+    pool.stats.acquire_finished(Duration::ZERO, true);
+    PooledClient {
+      name: pool_name.to_string(),
+      client: None,
+      manager: manager.clone(),
+      generation: pool.generation(),
+      pool,
+      in_transaction: false,
+      command_timeout: None,
+      replica,
+    }
+  }
+
+  #[tokio::test]
+  async fn closed_pools_reject_checkouts() {
+    let manager = manager_with_pools(&["main"]).await;
+    let handle = manager.pool("main").unwrap();
+    manager.close_pool("main").await.unwrap();
+    assert!(handle.is_closed());
+    let err = handle.get_client().await.err().unwrap();
+    assert!(err.to_string().contains("closed"), "{}", err);
+    assert!(manager.pool("main").is_err());
+  }
+
+  #[tokio::test]
+  async fn closing_a_primary_removes_its_groups() {
+    let manager = manager_with_pools(&["primary", "replica"]).await;
+    let group = manager
+      .init_group("orders", "primary", &["replica"], PoolGroupOptions::new())
+      .unwrap();
+    manager.close_pool("primary").await.unwrap();
+    assert!(manager.group("orders").is_err());
+    let err = group.write().await.err().unwrap();
+    assert!(err.to_string().contains("closed"), "{}", err);
+  }
+
+  #[tokio::test]
+  async fn closed_replicas_are_skipped() {
+    let manager = manager_with_pools(&["primary", "a", "b"]).await;
+    let options = PoolGroupOptions::new().fallback_to_primary(false);
+    let group = manager
+      .init_group("orders", "primary", &["a", "b"], options)
+      .unwrap();
+    let a = manager.pool("a").unwrap();
+    let b = manager.pool("b").unwrap();
+    manager.close_pool("a").await.unwrap();
+
+    assert!(manager.group("orders").is_ok());
+    assert!(group.read().await.is_err());
+    assert_eq!(a.stats().errors, 0);
+    assert_eq!(b.stats().errors, 1);
+  }
+
+  #[cfg(feature = "mssql")]
+  #[tokio::test]
+  async fn named_instances_are_resolved_through_the_sql_browser() {
+    let server = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let server_port = server.local_addr().unwrap().port();
+    let browser = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let browser_port = browser.local_addr().unwrap().port();
+    let browser = tokio::spawn(async move {
+      let mut request = [0u8; 64];
+      let (len, from) = browser.recv_from(&mut request).await.unwrap();
+      let body = format!(
+        "ServerName;DB;InstanceName;SQLEXPRESS;IsClustered;No;Version;16.0.1000.6;tcp;{};;",
+        server_port
+      );
+      let mut reply = vec![5u8];
+      reply.extend_from_slice(&(body.len() as u16).to_le_bytes());
+      reply.extend_from_slice(body.as_bytes());
+      browser.send_to(&reply, from).await.unwrap();
+      request[..len].to_vec()
+    });
+
+    let config = PoolConfig::new(Backend::Mssql)
+      .host("127.0.0.1")
+      .instance_name("SQLEXPRESS")
+      .sql_browser_port(browser_port)
+      .connect_timeout(Duration::from_secs(2));
+    let connect = tokio::spawn(async move { DbManager::connect(&config).await.map(drop) });
+
+    // The stub speaks no TDS, so reaching the resolved port is what is checked
+    let accepted = tokio::time::timeout(Duration::from_secs(2), server.accept()).await;
+    assert!(accepted.is_ok(), "no connection on the resolved port");
+    assert_eq!(browser.await.unwrap(), b"\x04SQLEXPRESS");
+    drop(accepted);
+    let _ = connect.await;
+  }
+
+  #[tokio::test]
+  async fn timeouts_of_discarded_connections_are_counted_once() {
+    let manager = manager_with_pools(&["main"]).await;
+    let handle = manager.pool("main").unwrap();
+    // A checked out client whose connection a statement inside the transaction already discarded
+    let mut client = discarded_client(&manager, "main", None);
+    let timeout = DbError::CommandTimeout(Duration::from_millis(10));
+    let result = client
+      .finish_attempt::<()>(Err(timeout.into()), CancelHandle::Discard)
+      .await;
+    assert!(matches!(
+      result.err().unwrap().downcast_ref::<DbError>(),
+      Some(DbError::CommandTimeout(_))
+    ));
+    let stats = handle.stats();
+    assert_eq!(stats.timeouts, 0);
+    assert_eq!(stats.connections_closed, 0);
+    drop(client);
+    assert_eq!(handle.stats().in_use, 0);
+  }
+}
@@ -0,0 +1,329 @@
+use anyhow::{Error, Result};
+use std::time::Duration;
+
+use crate::pool_manager::PooledClient;
+
+/// Classes of transient failures that a [`RetryPolicy`] can retry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(
+  feature = "serde",
+  derive(serde::Deserialize),
+  serde(rename_all = "snake_case")
+)]
+pub enum RetryableError {
+  /// The session was chosen as a deadlock victim (MSSQL 1205, PostgreSQL 40P01).
+  Deadlock,
+  /// The transaction could not be serialized (PostgreSQL 40001, MSSQL 3960).
+  SerializationFailure,
+  /// The connection was dropped or could not be (re)established.
+  ConnectionBroken,
+  /// The server stopped accepting writes after a failover (PostgreSQL 25006, MSSQL 3906, 976,
+  /// 983). Pools with several hosts reconnect to the new primary.
+  Failover,
+}
+
+impl RetryableError {
+  /// Every retryable error class.
+  pub const ALL: [RetryableError; 4] = [
+    RetryableError::Deadlock,
+    RetryableError::SerializationFailure,
+    RetryableError::ConnectionBroken,
+    RetryableError::Failover,
+  ];
+
+  /// Classify an error returned by a driver, if it is transient.
+  pub fn classify(err: &Error) -> Option<RetryableError> {
+    for cause in err.chain() {
+      #[cfg(feature = "mssql")]
+      if let Some(e) = cause.downcast_ref::<crate::types::sql::mssql::error::Error>() {
+        use crate::types::sql::mssql::error::Error as MssqlError;
+        return match e {
+          MssqlError::Server(token) => match token.code() {
+            1205 => Some(RetryableError::Deadlock),
+            3960 => Some(RetryableError::SerializationFailure),
+            3906 | 976 | 983 => Some(RetryableError::Failover),
+            _ => None,
+          },
+          MssqlError::Io { .. } => Some(RetryableError::ConnectionBroken),
+          _ => None,
+        };
+      }
+
+      #[cfg(feature = "pgsql")]
+      if let Some(e) = cause.downcast_ref::<crate::types::sql::pgsql::Error>() {
+        use crate::types::sql::pgsql::error::SqlState;
+        if e.is_closed() {
+          return Some(RetryableError::ConnectionBroken);
+        }
+        match e.code() {
+          Some(code) if *code == SqlState::T_R_DEADLOCK_DETECTED => {
+            return Some(RetryableError::Deadlock);
+          }
+          Some(code) if *code == SqlState::T_R_SERIALIZATION_FAILURE => {
+            return Some(RetryableError::SerializationFailure);
+          }
+          Some(code) if *code == SqlState::READ_ONLY_SQL_TRANSACTION => {
+            return Some(RetryableError::Failover);
+          }
+          Some(code)
+            if *code == SqlState::ADMIN_SHUTDOWN
+              || *code == SqlState::CRASH_SHUTDOWN
+              || *code == SqlState::CANNOT_CONNECT_NOW
+              || *code == SqlState::CONNECTION_FAILURE =>
+          {
+            return Some(RetryableError::ConnectionBroken);
+          }
+          Some(_) => return None,
+          // Fall through so an underlying I/O error is still recognised.
+          None => continue,
+        }
+      }
+
+      if cause.downcast_ref::<std::io::Error>().is_some() {
+        return Some(RetryableError::ConnectionBroken);
+      }
+    }
+    None
+  }
+}
+
+/// Retry policy applied by [`SqlRepo`](crate::SqlRepo) to calls made on a pool.
+///
+/// Deadlocks and serialization failures roll the statement back, so they are retried for every
+/// call. A broken connection leaves the outcome unknown, so it is only retried for idempotent
+/// calls (queries and whole transaction closures).
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize), serde(default))]
+pub struct RetryPolicy {
+  /// Total number of attempts, including the first one. `1` disables retries.
+  pub max_attempts: u32,
+  /// Delay before the first retry.
+  #[cfg_attr(
+    feature = "serde",
+    serde(deserialize_with = "crate::config::deserialize_duration")
+  )]
+  pub initial_backoff: Duration,
+  /// Upper bound for the delay between two attempts.
+  #[cfg_attr(
+    feature = "serde",
+    serde(deserialize_with = "crate::config::deserialize_duration")
+  )]
+  pub max_backoff: Duration,
+  /// Factor the delay grows by after each attempt.
+  pub multiplier: f64,
+  /// Randomize each delay between zero and the computed backoff.
+  pub jitter: bool,
+  /// Error classes that are retried.
+  pub retry_on: Vec<RetryableError>,
+}
+
+impl Default for RetryPolicy {
+  fn default() -> Self {
+    Self::disabled()
+  }
+}
+
+impl RetryPolicy {
+  /// Retry every transient error class up to `max_attempts` times with jittered exponential backoff.
+  pub fn new(max_attempts: u32) -> Self {
+    Self {
+      max_attempts: max_attempts.max(1),
+      initial_backoff: Duration::from_millis(50),
+      max_backoff: Duration::from_secs(2),
+      multiplier: 2.0,
+      jitter: true,
+      retry_on: RetryableError::ALL.to_vec(),
+    }
+  }
+
+  /// A policy that never retries.
+  pub fn disabled() -> Self {
+    Self::new(1)
+  }
+
+  pub fn with_backoff(mut self, initial: Duration, max: Duration) -> Self {
+    self.initial_backoff = initial;
+    self.max_backoff = max;
+    self
+  }
+
+  pub fn with_multiplier(mut self, multiplier: f64) -> Self {
+    self.multiplier = multiplier;
+    self
+  }
+
+  pub fn with_jitter(mut self, jitter: bool) -> Self {
+    self.jitter = jitter;
+    self
+  }
+
+  pub fn retry_on(mut self, classes: &[RetryableError]) -> Self {
+    self.retry_on = classes.to_vec();
+    self
+  }
+
+  /// Whether a failure of the given class on attempt `attempt` (1-based) should be retried.
+  pub fn should_retry(&self, class: RetryableError, attempt: u32, idempotent: bool) -> bool {
+    attempt < self.max_attempts
+      && self.retry_on.contains(&class)
+      && (idempotent || class != RetryableError::ConnectionBroken)
+  }
+
+  /// Delay to wait after the failed attempt `attempt` (1-based).
+  pub fn backoff(&self, attempt: u32) -> Duration {
+    let exp = self
+      .multiplier
+      .max(1.0)
+      .powi(attempt.saturating_sub(1) as i32);
+    let secs = (self.initial_backoff.as_secs_f64() * exp).min(self.max_backoff.as_secs_f64());
+    let delay = Duration::from_secs_f64(secs);
+    if self.jitter {
+      delay.mul_f64(fastrand::f64())
+    } else {
+      delay
+    }
+  }
+}
+
+/// Decide whether a failed attempt is retried, waiting out the backoff and replacing a broken
+/// connection (reads from a pool group move to another replica or the primary). Returns the
+/// error back when the call must fail.
+pub(crate) async fn prepare_retry(
+  pooled_client: &mut PooledClient,
+  mut err: Error,
+  attempt: &mut u32,
+  idempotent: bool,
+) -> Result<()> {
+  match RetryableError::classify(&err) {
+    Some(RetryableError::Failover) => pooled_client.retarget(),
+    Some(RetryableError::ConnectionBroken) => {
+      if let Some(replica) = &pooled_client.replica {
+        replica.connection_broken();
+      }
+    }
+    _ => {}
+  }
+  if pooled_client.in_transaction {
+    // The enclosing transaction is retried as a whole instead.
+    return Err(err);
+  }
+  let pool = pooled_client.pool.clone();
+  let policy = &pool.config.options.retry_policy;
+  loop {
+    let class = match RetryableError::classify(&err) {
+      Some(class) if policy.should_retry(class, *attempt, idempotent) => class,
+      _ => return Err(err),
+    };
+    let backoff = policy.backoff(*attempt);
+    #[cfg(feature = "tracing")]
+    tracing::warn!(attempt = *attempt, ?class, ?backoff, error = %err, "retrying after transient error");
+    tokio::time::sleep(backoff).await;
+    if class != RetryableError::ConnectionBroken {
+      return Ok(());
+    }
+    match pooled_client.replace_connection().await {
+      Ok(()) => return Ok(()),
+      Err(e) => {
+        *attempt += 1;
+        err = e;
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn backoff_grows_until_the_cap() {
+    let policy = RetryPolicy::new(5)
+      .with_backoff(Duration::from_millis(100), Duration::from_millis(350))
+      .with_jitter(false);
+    assert_eq!(policy.backoff(1), Duration::from_millis(100));
+    assert_eq!(policy.backoff(2), Duration::from_millis(200));
+    assert_eq!(policy.backoff(3), Duration::from_millis(350));
+    assert_eq!(policy.backoff(10), Duration::from_millis(350));
+  }
+
+  #[test]
+  fn backoff_never_shrinks_below_the_initial_delay() {
+    let policy = RetryPolicy::new(3)
+      .with_backoff(Duration::from_millis(100), Duration::from_secs(1))
+      .with_multiplier(0.5)
+      .with_jitter(false);
+    assert_eq!(policy.backoff(3), Duration::from_millis(100));
+  }
+
+  #[test]
+  fn jittered_backoff_stays_within_the_computed_delay() {
+    let policy =
+      RetryPolicy::new(3).with_backoff(Duration::from_millis(100), Duration::from_secs(1));
+    for _ in 0..100 {
+      assert!(policy.backoff(2) <= Duration::from_millis(200));
+    }
+  }
+
+  #[test]
+  fn should_retry_stops_after_max_attempts() {
+    let policy = RetryPolicy::new(3);
+    assert!(policy.should_retry(RetryableError::Deadlock, 1, false));
+    assert!(policy.should_retry(RetryableError::Deadlock, 2, false));
+    assert!(!policy.should_retry(RetryableError::Deadlock, 3, false));
+    assert!(!RetryPolicy::disabled().should_retry(RetryableError::Deadlock, 1, true));
+  }
+
+  #[test]
+  fn broken_connections_are_only_retried_for_idempotent_calls() {
+    let policy = RetryPolicy::new(3);
+    assert!(policy.should_retry(RetryableError::ConnectionBroken, 1, true));
+    assert!(!policy.should_retry(RetryableError::ConnectionBroken, 1, false));
+    assert!(policy.should_retry(RetryableError::SerializationFailure, 1, false));
+  }
+
+  #[test]
+  fn should_retry_honors_retry_on() {
+    let policy = RetryPolicy::new(3).retry_on(&[RetryableError::Deadlock]);
+    assert!(policy.should_retry(RetryableError::Deadlock, 1, true));
+    assert!(!policy.should_retry(RetryableError::Failover, 1, true));
+  }
+
+  #[test]
+  fn classify_io_errors_as_broken_connections() {
+    let err = Error::new(std::io::Error::from(std::io::ErrorKind::ConnectionReset));
+    assert_eq!(
+      RetryableError::classify(&err),
+      Some(RetryableError::ConnectionBroken)
+    );
+    let err = err.context("while running a query");
+    assert_eq!(
+      RetryableError::classify(&err),
+      Some(RetryableError::ConnectionBroken)
+    );
+  }
+
+  #[test]
+  fn classify_other_errors_as_permanent() {
+    assert_eq!(
+      RetryableError::classify(&anyhow::anyhow!("syntax error")),
+      None
+    );
+  }
+
+  #[cfg(feature = "mssql")]
+  #[test]
+  fn classify_mssql_errors() {
+    use crate::types::sql::mssql::error::{Error as MssqlError, IoErrorKind};
+
+    let io = Error::new(MssqlError::Io {
+      kind: IoErrorKind::UnexpectedEof,
+      message: "connection closed".into(),
+    });
+    assert_eq!(
+      RetryableError::classify(&io),
+      Some(RetryableError::ConnectionBroken)
+    );
+    let conversion = Error::new(MssqlError::Conversion("bad value".into()));
+    assert_eq!(RetryableError::classify(&conversion), None);
+  }
+}
@@ -0,0 +1,259 @@
+use std::{
+  sync::atomic::{AtomicU32, AtomicU64, Ordering},
+  time::Duration,
+};
+
+/// Upper bounds of the acquire latency histogram buckets.
+const LATENCY_BUCKETS: [Duration; 12] = [
+  Duration::from_micros(100),
+  Duration::from_micros(500),
+  Duration::from_millis(1),
+  Duration::from_millis(5),
+  Duration::from_millis(10),
+  Duration::from_millis(25),
+  Duration::from_millis(50),
+  Duration::from_millis(100),
+  Duration::from_millis(250),
+  Duration::from_millis(500),
+  Duration::from_secs(1),
+  Duration::from_secs(5),
+];
+
+/// Point-in-time view of a pool, returned by
+/// [`DbManager::stats`](crate::pool_manager::DbManager::stats).
+#[derive(Debug, Clone)]
+pub struct PoolStats {
+  /// Configured number of connections.
+  pub max_size: u32,
+  /// Open connections, idle or checked out.
+  pub total: u32,
+  /// Open connections waiting in the pool, computed as `total - in_use`. Both are read
+  /// separately without a lock, so this is approximate while checkouts run concurrently.
+  pub idle: u32,
+  /// Connections currently checked out.
+  pub in_use: u32,
+  /// Checkouts in progress, running `before_acquire` or opening a replacement connection.
+  /// These are not waiters: the pool has no wait queue, and a checkout from a pool whose
+  /// connections are all in use fails at once with `DbError::PoolExhausted` (counted in
+  /// `errors`) instead of waiting for one to be released.
+  pub acquiring: u32,
+  /// Successful checkouts.
+  pub acquires: u64,
+  /// Commands that hit their timeout.
+  pub timeouts: u64,
+  pub connections_created: u64,
+  pub connections_closed: u64,
+  /// Failed checkouts and failed `SqlRepo` calls.
+  pub errors: u64,
+  pub acquire_latency: LatencyHistogram,
+}
+
+/// Cumulative latency histogram, in the style of Prometheus.
+#[derive(Debug, Clone)]
+pub struct LatencyHistogram {
+  /// Number of observations at or below each upper bound.
+  pub buckets: Vec<(Duration, u64)>,
+  pub count: u64,
+  pub sum: Duration,
+}
+
+/// Counters kept by every pool, updated without locking.
+pub(crate) struct PoolCounters {
+  #[cfg_attr(not(feature = "metrics"), allow(dead_code))]
+  pool_name: String,
+  in_use: AtomicU32,
+  acquiring: AtomicU32,
+  acquires: AtomicU64,
+  timeouts: AtomicU64,
+  connections_created: AtomicU64,
+  connections_closed: AtomicU64,
+  errors: AtomicU64,
+  latency_buckets: [AtomicU64; LATENCY_BUCKETS.len()],
+  latency_count: AtomicU64,
+  latency_sum_nanos: AtomicU64,
+}
+
+impl PoolCounters {
+  pub(crate) fn new(pool_name: &str) -> Self {
+    Self {
+      pool_name: pool_name.to_string(),
+      in_use: AtomicU32::new(0),
+      acquiring: AtomicU32::new(0),
+      acquires: AtomicU64::new(0),
+      timeouts: AtomicU64::new(0),
+      connections_created: AtomicU64::new(0),
+      connections_closed: AtomicU64::new(0),
+      errors: AtomicU64::new(0),
+      latency_buckets: Default::default(),
+      latency_count: AtomicU64::new(0),
+      latency_sum_nanos: AtomicU64::new(0),
+    }
+  }
+
+  pub(crate) fn acquire_started(&self) {
+    self.acquiring.fetch_add(1, Ordering::Relaxed);
+    self.publish_gauges();
+  }
+
+  pub(crate) fn acquire_finished(&self, elapsed: Duration, succeeded: bool) {
+    self.acquiring.fetch_sub(1, Ordering::Relaxed);
+    if succeeded {
+      self.acquires.fetch_add(1, Ordering::Relaxed);
+      self.in_use.fetch_add(1, Ordering::Relaxed);
+    } else {
+      self.errors.fetch_add(1, Ordering::Relaxed);
+    }
+    if let Some(bucket) = LATENCY_BUCKETS.iter().position(|le| elapsed <= *le) {
+      self.latency_buckets[bucket].fetch_add(1, Ordering::Relaxed);
+    }
+    self.latency_count.fetch_add(1, Ordering::Relaxed);
+    self
+      .latency_sum_nanos
+      .fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+
+    #[cfg(feature = "metrics")]
+    {
+      let pool = self.pool_name.clone();
+      metrics::histogram!("db_pool_acquire_duration_seconds", "pool" => pool.clone())
+        .record(elapsed.as_secs_f64());
+      if succeeded {
+        metrics::counter!("db_pool_acquires_total", "pool" => pool).increment(1);
+      } else {
+        metrics::counter!("db_pool_errors_total", "pool" => pool).increment(1);
+      }
+    }
+    self.publish_gauges();
+  }
+
+  pub(crate) fn released(&self) {
+    self.in_use.fetch_sub(1, Ordering::Relaxed);
+    self.publish_gauges();
+  }
+
+  /// Connections currently checked out.
+  pub(crate) fn in_use(&self) -> u32 {
+    self.in_use.load(Ordering::Relaxed)
+  }
+
+  /// Push the current acquiring and in-use counts to the `metrics` recorder.
+  fn publish_gauges(&self) {
+    #[cfg(feature = "metrics")]
+    {
+      let pool = self.pool_name.clone();
+      metrics::gauge!("db_pool_acquiring", "pool" => pool.clone())
+        .set(self.acquiring.load(Ordering::Relaxed) as f64);
+      metrics::gauge!("db_pool_connections_in_use", "pool" => pool)
+        .set(self.in_use.load(Ordering::Relaxed) as f64);
+    }
+  }
+
+  pub(crate) fn timed_out(&self) {
+    self.timeouts.fetch_add(1, Ordering::Relaxed);
+    #[cfg(feature = "metrics")]
+    metrics::counter!("db_pool_timeouts_total", "pool" => self.pool_name.clone()).increment(1);
+  }
+
+  pub(crate) fn connection_created(&self) {
+    self.connections_created.fetch_add(1, Ordering::Relaxed);
+    #[cfg(feature = "metrics")]
+    metrics::counter!("db_pool_connections_created_total", "pool" => self.pool_name.clone())
+      .increment(1);
+  }
+
+  pub(crate) fn connection_closed(&self) {
+    self.connections_closed.fetch_add(1, Ordering::Relaxed);
+    #[cfg(feature = "metrics")]
+    metrics::counter!("db_pool_connections_closed_total", "pool" => self.pool_name.clone())
+      .increment(1);
+  }
+
+  pub(crate) fn error(&self) {
+    self.errors.fetch_add(1, Ordering::Relaxed);
+    #[cfg(feature = "metrics")]
+    metrics::counter!("db_pool_errors_total", "pool" => self.pool_name.clone()).increment(1);
+  }
+
+  /// Snapshot the counters, given the pool's size and number of open connections.
+  pub(crate) fn snapshot(&self, max_size: u32, total: u32) -> PoolStats {
+    let in_use = self.in_use.load(Ordering::Relaxed);
+    let mut cumulative = 0;
+    let buckets = LATENCY_BUCKETS
+      .iter()
+      .zip(&self.latency_buckets)
+      .map(|(le, count)| {
+        cumulative += count.load(Ordering::Relaxed);
+        (*le, cumulative)
+      })
+      .collect();
+    PoolStats {
+      max_size,
+      total,
+      idle: total.saturating_sub(in_use),
+      in_use,
+      acquiring: self.acquiring.load(Ordering::Relaxed),
+      acquires: self.acquires.load(Ordering::Relaxed),
+      timeouts: self.timeouts.load(Ordering::Relaxed),
+      connections_created: self.connections_created.load(Ordering::Relaxed),
+      connections_closed: self.connections_closed.load(Ordering::Relaxed),
+      errors: self.errors.load(Ordering::Relaxed),
+      acquire_latency: LatencyHistogram {
+        buckets,
+        count: self.latency_count.load(Ordering::Relaxed),
+        sum: Duration::from_nanos(self.latency_sum_nanos.load(Ordering::Relaxed)),
+      },
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn snapshot_tracks_checkouts() {
+    let counters = PoolCounters::new("test");
+    counters.acquire_started();
+    assert_eq!(counters.snapshot(4, 4).acquiring, 1);
+
+    counters.acquire_finished(Duration::from_millis(2), true);
+    let stats = counters.snapshot(4, 4);
+    assert_eq!((stats.acquiring, stats.in_use, stats.idle), (0, 1, 3));
+    assert_eq!(stats.acquires, 1);
+
+    counters.released();
+    assert_eq!(counters.snapshot(4, 4).idle, 4);
+  }
+
+  #[test]
+  fn failed_checkouts_count_as_errors() {
+    let counters = PoolCounters::new("test");
+    counters.acquire_started();
+    counters.acquire_finished(Duration::from_millis(2), false);
+    let stats = counters.snapshot(4, 4);
+    assert_eq!((stats.in_use, stats.acquires, stats.errors), (0, 0, 1));
+  }
+
+  #[test]
+  fn latency_histogram_is_cumulative() {
+    let counters = PoolCounters::new("test");
+    for elapsed in [
+      Duration::from_micros(50),
+      Duration::from_millis(3),
+      Duration::from_secs(10),
+    ] {
+      counters.acquire_started();
+      counters.acquire_finished(elapsed, true);
+    }
+    let latency = counters.snapshot(4, 4).acquire_latency;
+    let count_at = |le: Duration| latency.buckets.iter().find(|b| b.0 == le).unwrap().1;
+    assert_eq!(count_at(Duration::from_micros(100)), 1);
+    assert_eq!(count_at(Duration::from_millis(5)), 2);
+    // Slower than the last bucket: only in the count and sum
+    assert_eq!(count_at(Duration::from_secs(5)), 2);
+    assert_eq!(latency.count, 3);
+    assert_eq!(
+      latency.sum,
+      Duration::from_micros(50) + Duration::from_millis(3) + Duration::from_secs(10)
+    );
+  }
+}
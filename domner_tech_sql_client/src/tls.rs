@@ -0,0 +1,219 @@
+use anyhow::Result;
+use std::{path::PathBuf, str::FromStr};
+
+/// libpq `sslmode` values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(
+  feature = "serde",
+  derive(serde::Deserialize),
+  serde(rename_all = "kebab-case")
+)]
+pub enum PgSslMode {
+  /// Never use TLS.
+  Disable,
+  /// Use TLS if the server supports it, without verifying the certificate.
+  #[default]
+  #[cfg_attr(feature = "serde", serde(alias = "allow"))]
+  Prefer,
+  /// Always use TLS, without verifying the certificate (unless a root certificate is given).
+  Require,
+  /// Always use TLS and verify the certificate chain, but not the host name.
+  VerifyCa,
+  /// Always use TLS and verify both the certificate chain and the host name.
+  VerifyFull,
+}
+
+impl FromStr for PgSslMode {
+  type Err = anyhow::Error;
+
+  fn from_str(s: &str) -> Result<Self> {
+    match s {
+      "disable" => Ok(PgSslMode::Disable),
+      // tokio-postgres cannot fall back from plain text to TLS, `prefer` is the closest.
+      "allow" | "prefer" => Ok(PgSslMode::Prefer),
+      "require" => Ok(PgSslMode::Require),
+      "verify-ca" => Ok(PgSslMode::VerifyCa),
+      "verify-full" => Ok(PgSslMode::VerifyFull),
+      _ => Err(anyhow::anyhow!("Invalid sslmode `{}`", s)),
+    }
+  }
+}
+
+/// TLS settings of a PostgreSQL connection, read from the libpq `sslmode`, `sslrootcert`,
+/// `sslcert` and `sslkey` parameters (see
+/// [`PgConnectParams::tls_options`](crate::config::PgConnectParams::tls_options)).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(
+  feature = "serde",
+  derive(serde::Deserialize),
+  serde(default, deny_unknown_fields)
+)]
+pub struct PgTlsOptions {
+  pub mode: PgSslMode,
+  /// PEM file with the certificate authorities to trust instead of the system ones.
+  /// `system` keeps the system roots, as in libpq.
+  pub root_cert: Option<PathBuf>,
+  /// PEM client certificate, used together with `client_key`.
+  pub client_cert: Option<PathBuf>,
+  /// PEM (PKCS#8) private key of the client certificate.
+  pub client_key: Option<PathBuf>,
+}
+
+impl PgTlsOptions {
+  /// Apply one libpq parameter, returning whether it was a TLS parameter
+  pub fn set(&mut self, key: &str, value: &str) -> Result<bool> {
+    match key {
+      "sslmode" => self.mode = value.parse()?,
+      "sslrootcert" => self.root_cert = Some(PathBuf::from(value)),
+      "sslcert" => self.client_cert = Some(PathBuf::from(value)),
+      "sslkey" => self.client_key = Some(PathBuf::from(value)),
+      _ => return Ok(false),
+    }
+    Ok(true)
+  }
+
+  /// Mode actually enforced: like libpq, `require` verifies the chain when a root
+  /// certificate file is given.
+  pub fn effective_mode(&self) -> PgSslMode {
+    match self.mode {
+      PgSslMode::Require if self.custom_root_cert().is_some() => PgSslMode::VerifyCa,
+      mode => mode,
+    }
+  }
+
+  fn custom_root_cert(&self) -> Option<&PathBuf> {
+    self
+      .root_cert
+      .as_ref()
+      .filter(|path| path.as_os_str() != "system")
+  }
+
+  /// Build the connector used for the TLS handshake (and for cancel requests)
+  #[cfg(feature = "pgsql")]
+  pub(crate) fn connector(&self) -> Result<postgres_native_tls::MakeTlsConnector> {
+    let mut builder = native_tls::TlsConnector::builder();
+    match self.effective_mode() {
+      PgSslMode::Disable | PgSslMode::Prefer | PgSslMode::Require => {
+        builder
+          .danger_accept_invalid_certs(true)
+          .danger_accept_invalid_hostnames(true);
+      }
+      PgSslMode::VerifyCa => {
+        builder.danger_accept_invalid_hostnames(true);
+      }
+      PgSslMode::VerifyFull => {}
+    }
+    if let Some(path) = self.custom_root_cert() {
+      let pem = std::fs::read(path)
+        .map_err(|e| anyhow::anyhow!("Failed to read sslrootcert {}: {}", path.display(), e))?;
+      builder.disable_built_in_roots(true);
+      for cert in split_pem_certificates(&pem) {
+        builder.add_root_certificate(native_tls::Certificate::from_pem(cert)?);
+      }
+    }
+    match (&self.client_cert, &self.client_key) {
+      (Some(cert), Some(key)) => {
+        let cert = std::fs::read(cert)
+          .map_err(|e| anyhow::anyhow!("Failed to read sslcert {}: {}", cert.display(), e))?;
+        let key = std::fs::read(key)
+          .map_err(|e| anyhow::anyhow!("Failed to read sslkey {}: {}", key.display(), e))?;
+        builder.identity(native_tls::Identity::from_pkcs8(&cert, &key)?);
+      }
+      (None, None) => {}
+      _ => return Err(anyhow::anyhow!("sslcert and sslkey must be given together")),
+    }
+    Ok(postgres_native_tls::MakeTlsConnector::new(builder.build()?))
+  }
+
+  /// `sslmode` as understood by tokio-postgres; certificate checks are done by the connector
+  #[cfg(feature = "pgsql")]
+  pub(crate) fn ssl_mode(&self) -> crate::types::sql::pgsql::config::SslMode {
+    use crate::types::sql::pgsql::config::SslMode;
+    match self.mode {
+      PgSslMode::Disable => SslMode::Disable,
+      PgSslMode::Prefer => SslMode::Prefer,
+      PgSslMode::Require | PgSslMode::VerifyCa | PgSslMode::VerifyFull => SslMode::Require,
+    }
+  }
+}
+
+/// Split a PEM bundle into its individual certificates
+#[cfg(feature = "pgsql")]
+fn split_pem_certificates(pem: &[u8]) -> Vec<&[u8]> {
+  const END: &[u8] = b"-----END CERTIFICATE-----";
+  let mut certs = Vec::new();
+  let mut rest = pem;
+  while let Some(pos) = rest.windows(END.len()).position(|w| w == END) {
+    certs.push(&rest[..pos + END.len()]);
+    rest = &rest[pos + END.len()..];
+  }
+  certs
+}
+
+/// TLS settings of an MSSQL connection, read from the `Encrypt`, `TrustServerCertificate` and
+/// `TrustServerCertificateCA` connection string keys.
+///
+/// By default the login is encrypted and the server certificate validated against the system
+/// store; the rest of the session is only encrypted if the server requires it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(
+  feature = "serde",
+  derive(serde::Deserialize),
+  serde(default, deny_unknown_fields)
+)]
+pub struct MssqlTlsOptions {
+  /// Certificate (`pem`, `crt` or `der`) to trust in addition to the system store.
+  pub ca_cert: Option<PathBuf>,
+  /// Encrypt the whole session (`Encrypt=true`).
+  pub require_encryption: bool,
+  /// Accept any server certificate (`TrustServerCertificate=true`). Not for production.
+  pub trust_server_certificate: bool,
+  /// Do not encrypt at all, not even the login (`Encrypt=DANGER_PLAINTEXT`).
+  pub danger_plaintext: bool,
+}
+
+impl MssqlTlsOptions {
+  pub fn ca_cert(mut self, path: impl Into<PathBuf>) -> Self {
+    self.ca_cert = Some(path.into());
+    self
+  }
+
+  pub fn require_encryption(mut self, require_encryption: bool) -> Self {
+    self.require_encryption = require_encryption;
+    self
+  }
+
+  pub fn trust_server_certificate(mut self, trust_server_certificate: bool) -> Self {
+    self.trust_server_certificate = trust_server_certificate;
+    self
+  }
+
+  #[cfg(feature = "mssql")]
+  pub(crate) fn apply(&self, config: &mut crate::types::sql::mssql::Config) -> Result<()> {
+    use crate::types::sql::mssql::EncryptionLevel;
+
+    if self.trust_server_certificate && self.ca_cert.is_some() {
+      return Err(anyhow::anyhow!(
+        "TrustServerCertificate and TrustServerCertificateCA are mutually exclusive"
+      ));
+    }
+    if self.danger_plaintext && self.require_encryption {
+      return Err(anyhow::anyhow!(
+        "Encryption cannot be both required and disabled"
+      ));
+    }
+    config.encryption(if self.danger_plaintext {
+      EncryptionLevel::NotSupported
+    } else if self.require_encryption {
+      EncryptionLevel::Required
+    } else {
+      EncryptionLevel::Off
+    });
+    if self.trust_server_certificate {
+      config.trust_cert();
+    } else if let Some(path) = &self.ca_cert {
+      config.trust_cert_ca(path.display());
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,88 @@
+//! Parameter encodings for values a driver has no mapping of its own for.
+
+#[cfg(feature = "mssql")]
+use chrono::{DateTime, Local};
+use ref_cast::RefCast;
+
+#[cfg(feature = "mssql")]
+use crate::types::sql::mssql;
+#[cfg(feature = "pgsql")]
+use crate::types::sql::pgsql;
+
+/// A parameter bound through this crate's own encoding rather than the driver's
+#[derive(Debug, RefCast)]
+#[repr(transparent)]
+pub(crate) struct Adapted<T>(T);
+
+/// SQL `NULL` accepted for a parameter of any PostgreSQL type
+#[cfg(feature = "pgsql")]
+#[derive(Debug)]
+pub(crate) struct PgNull;
+
+#[cfg(feature = "pgsql")]
+impl pgsql::types::ToSql for PgNull {
+  fn to_sql(
+    &self,
+    _: &pgsql::types::Type,
+    _: &mut bytes::BytesMut,
+  ) -> Result<pgsql::types::IsNull, Box<dyn std::error::Error + Sync + Send>> {
+    Ok(pgsql::types::IsNull::Yes)
+  }
+
+  fn accepts(_: &pgsql::types::Type) -> bool {
+    true
+  }
+
+  pgsql::types::to_sql_checked!();
+}
+
+/// `TINYINT` is unsigned, so `i8` is sent as `SMALLINT`
+#[cfg(feature = "mssql")]
+impl mssql::ToSql for Adapted<i8> {
+  fn to_sql(&self) -> mssql::ColumnData<'_> {
+    mssql::ColumnData::I16(Some(self.0.into()))
+  }
+}
+
+/// Sent as `DATETIMEOFFSET` with the local offset
+#[cfg(feature = "mssql")]
+impl mssql::ToSql for Adapted<DateTime<Local>> {
+  fn to_sql(&self) -> mssql::ColumnData<'_> {
+    mssql::IntoSql::into_sql(self.0.fixed_offset())
+  }
+}
+
+/// PostgreSQL has no one-byte integer (`i8` is its internal `"char"` type), so `i8` and `u8`
+/// are widened to whichever integer type the parameter has
+#[cfg(feature = "pgsql")]
+macro_rules! pg_small_int {
+  ($($ty:ty),*) => {
+    $(
+      impl pgsql::types::ToSql for Adapted<$ty> {
+        fn to_sql(
+          &self,
+          ty: &pgsql::types::Type,
+          out: &mut bytes::BytesMut,
+        ) -> Result<pgsql::types::IsNull, Box<dyn std::error::Error + Sync + Send>> {
+          match *ty {
+            pgsql::types::Type::INT2 => i16::from(self.0).to_sql(ty, out),
+            pgsql::types::Type::INT4 => i32::from(self.0).to_sql(ty, out),
+            _ => i64::from(self.0).to_sql(ty, out),
+          }
+        }
+
+        fn accepts(ty: &pgsql::types::Type) -> bool {
+          matches!(
+            *ty,
+            pgsql::types::Type::INT2 | pgsql::types::Type::INT4 | pgsql::types::Type::INT8
+          )
+        }
+
+        pgsql::types::to_sql_checked!();
+      }
+    )*
+  };
+}
+
+#[cfg(feature = "pgsql")]
+pg_small_int!(i8, u8);
@@ -0,0 +1,72 @@
+use std::marker::PhantomData;
+
+use super::{Result, UnifiedToSql};
+#[cfg(feature = "pgsql")]
+use crate::types::adapters::PgNull;
+#[cfg(feature = "mssql")]
+use crate::types::sql::mssql;
+#[cfg(feature = "pgsql")]
+use crate::types::sql::pgsql;
+
+/// A list parameter for `IN (...)`. Its placeholder is replaced by one placeholder per item, so
+/// `WHERE id IN (@P1)` with `InList(&[4, 5, 6])` runs as `WHERE id IN (@P1, @P2, @P3)` and
+/// later placeholders are renumbered. An empty list matches no rows with `IN` and every row with
+/// `NOT IN`.
+///
+/// Only expanded in the text of [`SqlRepo`](crate::SqlRepo) commands and queries; on PostgreSQL,
+/// binding a `Vec<T>` to `= ANY($1)` keeps a single parameter however long the list is.
+#[derive(Debug, Clone, Copy)]
+pub struct InList<'a, T>(pub &'a [T]);
+
+impl<T: UnifiedToSql> UnifiedToSql for InList<'_, T> {
+  #[cfg(feature = "mssql")]
+  fn to_mssql_param(&self) -> Result<&dyn mssql::ToSql> {
+    Err(not_expanded())
+  }
+  #[cfg(feature = "pgsql")]
+  fn to_pgsql_param(&self) -> Result<&(dyn pgsql::types::ToSql + Sync)> {
+    Err(not_expanded())
+  }
+
+  fn in_list(&self) -> Option<ListItems<'_>> {
+    Some(ListItems {
+      items: self
+        .0
+        .iter()
+        .map(|item| item as &dyn UnifiedToSql)
+        .collect(),
+      null: &TypedNull::<T>::NULL,
+    })
+  }
+}
+
+/// The items of a list parameter
+pub struct ListItems<'a> {
+  pub items: Vec<&'a dyn UnifiedToSql>,
+  /// `NULL` of the item type, bound when there are no items so the statement still has a typed
+  /// parameter to compare against
+  pub null: &'a dyn UnifiedToSql,
+}
+
+/// `NULL` typed as `T`
+struct TypedNull<T>(PhantomData<fn() -> T>);
+
+impl<T> TypedNull<T> {
+  const NULL: Self = TypedNull(PhantomData);
+}
+
+impl<T: UnifiedToSql> UnifiedToSql for TypedNull<T> {
+  #[cfg(feature = "mssql")]
+  fn to_mssql_param(&self) -> Result<&dyn mssql::ToSql> {
+    Ok(T::mssql_null())
+  }
+  #[cfg(feature = "pgsql")]
+  fn to_pgsql_param(&self) -> Result<&(dyn pgsql::types::ToSql + Sync)> {
+    Ok(&PgNull)
+  }
+}
+
+#[cfg(any(feature = "mssql", feature = "pgsql"))]
+fn not_expanded() -> anyhow::Error {
+  anyhow::anyhow!("An `InList` can only be bound in the text of a command or query")
+}
@@ -0,0 +1,134 @@
+use ref_cast::RefCast;
+use serde::{Deserialize, Serialize};
+use std::fmt::Debug;
+
+#[cfg(feature = "mssql")]
+use crate::types::sql::mssql;
+#[cfg(feature = "pgsql")]
+use crate::types::sql::pgsql;
+
+/// A value stored as JSON: `json`/`jsonb` on PostgreSQL, `NVARCHAR(MAX)` text on MSSQL.
+///
+/// Binds any `Serialize` type as a parameter and reads a JSON column back into any `Deserialize`
+/// type, e.g. `row.get_mssql::<Json<Order>>("payload")?.0`. On MSSQL the value is serialized
+/// when the parameters are bound, so a value that cannot be serialized fails the call.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, RefCast)]
+#[serde(transparent)]
+#[repr(transparent)]
+pub struct Json<T>(pub T);
+
+impl<T> Json<T> {
+  /// View a value as a `Json` without copying it
+  pub fn from_ref(value: &T) -> &Self {
+    Self::ref_cast(value)
+  }
+
+  pub fn into_inner(self) -> T {
+    self.0
+  }
+}
+
+#[cfg(feature = "mssql")]
+impl<'a, T> mssql::FromSql<'a> for Json<T>
+where
+  T: Deserialize<'a> + 'a,
+{
+  fn from_sql(value: &'a mssql::ColumnData<'static>) -> mssql::Result<Option<Self>> {
+    match value {
+      mssql::ColumnData::String(text) => text
+        .as_deref()
+        .map(|text| serde_json::from_str(text).map(Json))
+        .transpose()
+        .map_err(|e| mssql::error::Error::Conversion(format!("Invalid JSON: {}", e).into())),
+      v => Err(mssql::error::Error::Conversion(
+        format!("cannot interpret {:?} as JSON", v).into(),
+      )),
+    }
+  }
+}
+
+#[cfg(feature = "pgsql")]
+impl<T> pgsql::types::ToSql for Json<T>
+where
+  T: Serialize + Debug,
+{
+  fn to_sql(
+    &self,
+    ty: &pgsql::types::Type,
+    out: &mut bytes::BytesMut,
+  ) -> Result<pgsql::types::IsNull, Box<dyn std::error::Error + Sync + Send>> {
+    pgsql::types::Json(&self.0).to_sql(ty, out)
+  }
+
+  fn accepts(ty: &pgsql::types::Type) -> bool {
+    <pgsql::types::Json<T> as pgsql::types::ToSql>::accepts(ty)
+  }
+
+  pgsql::types::to_sql_checked!();
+}
+
+#[cfg(feature = "pgsql")]
+impl<'a, T> pgsql::types::FromSql<'a> for Json<T>
+where
+  T: Deserialize<'a>,
+{
+  fn from_sql(
+    ty: &pgsql::types::Type,
+    raw: &'a [u8],
+  ) -> Result<Self, Box<dyn std::error::Error + Sync + Send>> {
+    pgsql::types::Json::<T>::from_sql(ty, raw).map(|json| Json(json.0))
+  }
+
+  fn accepts(ty: &pgsql::types::Type) -> bool {
+    <pgsql::types::Json<T> as pgsql::types::FromSql>::accepts(ty)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  #[cfg(feature = "mssql")]
+  use crate::types::UnifiedToSql;
+  #[cfg(feature = "mssql")]
+  use std::collections::BTreeMap;
+
+  #[test]
+  fn from_ref_views_the_value() {
+    let value = vec![1, 2];
+    assert_eq!(Json::from_ref(&value).0, [1, 2]);
+  }
+
+  #[cfg(feature = "mssql")]
+  #[test]
+  fn mssql_parameters_are_serialized_before_binding() {
+    use crate::types::{mssql_json_texts, mssql_params};
+
+    let order = Json(BTreeMap::from([("id", 7)]));
+    let missing: Option<Json<Vec<i32>>> = None;
+    let params: [&dyn UnifiedToSql; 2] = [&order, &missing];
+    let texts = mssql_json_texts(&params).unwrap();
+    assert_eq!(texts, [Some(r#"{"id":7}"#.to_string()), None]);
+
+    let bound = mssql_params(&params, &texts).unwrap();
+    assert_eq!(
+      bound[0].to_sql(),
+      mssql::ColumnData::String(Some(r#"{"id":7}"#.into()))
+    );
+    assert_eq!(bound[1].to_sql(), mssql::ColumnData::String(None));
+  }
+
+  #[cfg(feature = "mssql")]
+  #[test]
+  fn unserializable_mssql_parameters_fail_the_call() {
+    // JSON object keys must be strings
+    let pairs = Json(BTreeMap::from([((1, 2), "a")]));
+    let err = pairs.mssql_json().unwrap().unwrap_err();
+    assert!(
+      err.to_string().contains("Invalid JSON parameter"),
+      "{}",
+      err
+    );
+    assert!(crate::types::mssql_json_texts(&[&pairs]).is_err());
+    assert!(pairs.to_mssql_param().is_err());
+  }
+}
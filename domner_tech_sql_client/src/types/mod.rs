@@ -1,5 +1,12 @@
+#[cfg(any(feature = "mssql", feature = "pgsql"))]
+mod adapters;
 pub mod decimal;
+mod in_list;
+#[cfg(any(feature = "mssql", feature = "pgsql"))]
+mod json;
 pub mod sql;
+#[cfg(feature = "mssql")]
+mod table_valued;
 pub mod uuid;
 
 #[cfg(feature = "mssql")]
@@ -9,330 +16,463 @@ use crate::types::sql::mssql;
 use crate::types::sql::pgsql;
 
 pub use anyhow::Result;
-use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+use chrono::{DateTime, FixedOffset, Local, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+use std::borrow::Cow;
+
+#[cfg(any(feature = "mssql", feature = "pgsql"))]
+use adapters::Adapted;
+#[cfg(feature = "pgsql")]
+use adapters::PgNull;
+use decimal::Decimal;
+pub use in_list::{InList, ListItems};
+#[cfg(any(feature = "mssql", feature = "pgsql"))]
+pub use json::Json;
+#[cfg(any(feature = "mssql", feature = "pgsql"))]
+use ref_cast::RefCast;
+#[cfg(any(feature = "mssql", feature = "pgsql"))]
 use serde_json::Value;
-pub trait UnifiedToSql {
+#[cfg(feature = "mssql")]
+pub use table_valued::TableValuedParam;
+/// A value that can be bound as a parameter on every enabled backend.
+///
+/// Implemented for the common scalar types, `Option<T>` and `&T` of any of them, `Json<T>` and
+/// `serde_json::Value`. Values are `Sync`, so calls binding them can run in spawned tasks.
+pub trait UnifiedToSql: Sync {
   #[cfg(feature = "mssql")]
   fn to_mssql_param(&self) -> Result<&dyn mssql::ToSql>;
   #[cfg(feature = "pgsql")]
   fn to_pgsql_param(&self) -> Result<&(dyn pgsql::types::ToSql + Sync)>;
-}
 
-impl UnifiedToSql for i16 {
+  /// `NULL` of this type, bound for `None`. MSSQL declares the type of every parameter and only
+  /// converts `NULL` between compatible types, e.g. not from `NVARCHAR` to `VARBINARY`.
   #[cfg(feature = "mssql")]
-  fn to_mssql_param(&self) -> Result<&dyn mssql::ToSql> {
-    Ok(self)
-  }
-  #[cfg(feature = "pgsql")]
-  fn to_pgsql_param(&self) -> Result<&(dyn pgsql::types::ToSql + Sync)> {
-    Ok(self)
+  fn mssql_null() -> &'static dyn mssql::ToSql
+  where
+    Self: Sized,
+  {
+    &None::<&str>
   }
-}
 
-impl UnifiedToSql for Option<i16> {
+  /// JSON text bound in place of this parameter on MSSQL, serialized before the parameters are
+  /// bound so that a failure is reported. Only JSON values and arrays have any.
   #[cfg(feature = "mssql")]
-  fn to_mssql_param(&self) -> Result<&dyn mssql::ToSql> {
-    Ok(self)
+  fn mssql_json(&self) -> Option<Result<String>> {
+    None
   }
-  #[cfg(feature = "pgsql")]
-  fn to_pgsql_param(&self) -> Result<&(dyn pgsql::types::ToSql + Sync)> {
-    Ok(self)
+
+  /// Items bound in place of this parameter, each with a placeholder of its own. Only
+  /// [`InList`] has any.
+  fn in_list(&self) -> Option<ListItems<'_>> {
+    None
   }
-}
 
-impl UnifiedToSql for i32 {
+  /// Rows bound as a table variable in place of this parameter. Only [`TableValuedParam`] has
+  /// any.
   #[cfg(feature = "mssql")]
-  fn to_mssql_param(&self) -> Result<&dyn mssql::ToSql> {
-    Ok(self)
-  }
-  #[cfg(feature = "pgsql")]
-  fn to_pgsql_param(&self) -> Result<&(dyn pgsql::types::ToSql + Sync)> {
-    Ok(self)
+  fn table_valued(&self) -> Option<&TableValuedParam<'_>> {
+    None
   }
 }
 
-impl UnifiedToSql for Option<i32> {
+impl<T: UnifiedToSql> UnifiedToSql for Option<T> {
   #[cfg(feature = "mssql")]
   fn to_mssql_param(&self) -> Result<&dyn mssql::ToSql> {
-    Ok(self)
+    match self {
+      Some(value) => value.to_mssql_param(),
+      None => Ok(T::mssql_null()),
+    }
   }
   #[cfg(feature = "pgsql")]
   fn to_pgsql_param(&self) -> Result<&(dyn pgsql::types::ToSql + Sync)> {
-    Ok(self)
+    match self {
+      Some(value) => value.to_pgsql_param(),
+      None => Ok(&PgNull),
+    }
   }
-}
-
-impl UnifiedToSql for i64 {
   #[cfg(feature = "mssql")]
-  fn to_mssql_param(&self) -> Result<&dyn mssql::ToSql> {
-    Ok(self)
+  fn mssql_null() -> &'static dyn mssql::ToSql {
+    T::mssql_null()
   }
-  #[cfg(feature = "pgsql")]
-  fn to_pgsql_param(&self) -> Result<&(dyn pgsql::types::ToSql + Sync)> {
-    Ok(self)
+  #[cfg(feature = "mssql")]
+  fn mssql_json(&self) -> Option<Result<String>> {
+    self.as_ref().and_then(T::mssql_json)
   }
 }
 
-impl UnifiedToSql for Option<i64> {
+impl<T: UnifiedToSql> UnifiedToSql for &T {
   #[cfg(feature = "mssql")]
   fn to_mssql_param(&self) -> Result<&dyn mssql::ToSql> {
-    Ok(self)
+    (**self).to_mssql_param()
   }
   #[cfg(feature = "pgsql")]
   fn to_pgsql_param(&self) -> Result<&(dyn pgsql::types::ToSql + Sync)> {
-    Ok(self)
+    (**self).to_pgsql_param()
   }
-}
-
-impl UnifiedToSql for f32 {
   #[cfg(feature = "mssql")]
-  fn to_mssql_param(&self) -> Result<&dyn mssql::ToSql> {
-    Ok(self)
+  fn mssql_null() -> &'static dyn mssql::ToSql {
+    T::mssql_null()
   }
-  #[cfg(feature = "pgsql")]
-  fn to_pgsql_param(&self) -> Result<&(dyn pgsql::types::ToSql + Sync)> {
-    Ok(self)
-  }
-}
-
-impl UnifiedToSql for Option<f32> {
   #[cfg(feature = "mssql")]
-  fn to_mssql_param(&self) -> Result<&dyn mssql::ToSql> {
-    Ok(self)
+  fn mssql_json(&self) -> Option<Result<String>> {
+    (**self).mssql_json()
   }
-  #[cfg(feature = "pgsql")]
-  fn to_pgsql_param(&self) -> Result<&(dyn pgsql::types::ToSql + Sync)> {
-    Ok(self)
+  fn in_list(&self) -> Option<ListItems<'_>> {
+    (**self).in_list()
   }
-}
-
-impl UnifiedToSql for f64 {
   #[cfg(feature = "mssql")]
-  fn to_mssql_param(&self) -> Result<&dyn mssql::ToSql> {
-    Ok(self)
-  }
-  #[cfg(feature = "pgsql")]
-  fn to_pgsql_param(&self) -> Result<&(dyn pgsql::types::ToSql + Sync)> {
-    Ok(self)
+  fn table_valued(&self) -> Option<&TableValuedParam<'_>> {
+    (**self).table_valued()
   }
 }
 
-impl UnifiedToSql for Option<f64> {
-  #[cfg(feature = "mssql")]
-  fn to_mssql_param(&self) -> Result<&dyn mssql::ToSql> {
-    Ok(self)
-  }
-  #[cfg(feature = "pgsql")]
-  fn to_pgsql_param(&self) -> Result<&(dyn pgsql::types::ToSql + Sync)> {
-    Ok(self)
-  }
+/// Types both drivers bind directly, with the type whose `None` is their MSSQL `NULL`
+macro_rules! unified_to_sql {
+  ($($ty:ty => $null:ty),* $(,)?) => {
+    $(
+      impl UnifiedToSql for $ty {
+        #[cfg(feature = "mssql")]
+        fn to_mssql_param(&self) -> Result<&dyn mssql::ToSql> {
+          Ok(self)
+        }
+        #[cfg(feature = "pgsql")]
+        fn to_pgsql_param(&self) -> Result<&(dyn pgsql::types::ToSql + Sync)> {
+          Ok(self)
+        }
+        #[cfg(feature = "mssql")]
+        fn mssql_null() -> &'static dyn mssql::ToSql {
+          &None::<$null>
+        }
+      }
+    )*
+  };
 }
 
-impl UnifiedToSql for Vec<u8> {
-  #[cfg(feature = "mssql")]
-  fn to_mssql_param(&self) -> Result<&dyn mssql::ToSql> {
-    Ok(self)
-  }
-  #[cfg(feature = "pgsql")]
-  fn to_pgsql_param(&self) -> Result<&(dyn pgsql::types::ToSql + Sync)> {
-    Ok(self)
-  }
+// Declared after the macro it uses
+#[cfg(feature = "time")]
+mod time;
+
+unified_to_sql!(
+  bool => bool,
+  i16 => i16,
+  i32 => i32,
+  i64 => i64,
+  f32 => f32,
+  f64 => f64,
+  Decimal => Decimal,
+  &str => &str,
+  String => &str,
+  Cow<'_, str> => &str,
+  &[u8] => &[u8],
+  Vec<u8> => &[u8],
+  uuid::Uuid => uuid::Uuid,
+  NaiveDate => NaiveDate,
+  NaiveTime => NaiveTime,
+  NaiveDateTime => NaiveDateTime,
+  DateTime<Utc> => DateTime<Utc>,
+  DateTime<FixedOffset> => DateTime<FixedOffset>,
+);
+
+/// `Vec<T>` and `&[T]` of types both drivers bind directly: a PostgreSQL array
+/// (`WHERE id = ANY($1)`), and on MSSQL, which has no arrays, JSON array text to unpack with
+/// `OPENJSON` (`WHERE id IN (SELECT value FROM OPENJSON(@P1))`)
+macro_rules! unified_array {
+  ($($ty:ty),* $(,)?) => {
+    $(
+      impl UnifiedToSql for Vec<$ty> {
+        #[cfg(feature = "mssql")]
+        fn to_mssql_param(&self) -> Result<&dyn mssql::ToSql> {
+          Err(json_unbound())
+        }
+        #[cfg(feature = "pgsql")]
+        fn to_pgsql_param(&self) -> Result<&(dyn pgsql::types::ToSql + Sync)> {
+          Ok(self)
+        }
+        #[cfg(feature = "mssql")]
+        fn mssql_json(&self) -> Option<Result<String>> {
+          Some(json_text(self))
+        }
+      }
+
+      impl UnifiedToSql for &[$ty] {
+        #[cfg(feature = "mssql")]
+        fn to_mssql_param(&self) -> Result<&dyn mssql::ToSql> {
+          Err(json_unbound())
+        }
+        #[cfg(feature = "pgsql")]
+        fn to_pgsql_param(&self) -> Result<&(dyn pgsql::types::ToSql + Sync)> {
+          Ok(self)
+        }
+        #[cfg(feature = "mssql")]
+        fn mssql_json(&self) -> Option<Result<String>> {
+          Some(json_text(self))
+        }
+      }
+    )*
+  };
 }
 
-impl UnifiedToSql for Option<Vec<u8>> {
+// `Vec<u8>` and `&[u8]` are binary values rather than arrays
+unified_array!(
+  bool,
+  i16,
+  i32,
+  i64,
+  f32,
+  f64,
+  Decimal,
+  &str,
+  String,
+  uuid::Uuid,
+  NaiveDate,
+  NaiveTime,
+  NaiveDateTime,
+  DateTime<Utc>,
+  DateTime<FixedOffset>,
+);
+
+impl UnifiedToSql for i8 {
   #[cfg(feature = "mssql")]
   fn to_mssql_param(&self) -> Result<&dyn mssql::ToSql> {
-    Ok(self)
+    Ok(Adapted::ref_cast(self))
   }
   #[cfg(feature = "pgsql")]
   fn to_pgsql_param(&self) -> Result<&(dyn pgsql::types::ToSql + Sync)> {
-    Ok(self)
+    Ok(Adapted::ref_cast(self))
   }
-}
-
-impl UnifiedToSql for &str {
   #[cfg(feature = "mssql")]
-  fn to_mssql_param(&self) -> Result<&dyn mssql::ToSql> {
-    Ok(self)
-  }
-  #[cfg(feature = "pgsql")]
-  fn to_pgsql_param(&self) -> Result<&(dyn pgsql::types::ToSql + Sync)> {
-    Ok(self)
+  fn mssql_null() -> &'static dyn mssql::ToSql {
+    &None::<i16>
   }
 }
 
-impl UnifiedToSql for Option<&str> {
+impl UnifiedToSql for u8 {
   #[cfg(feature = "mssql")]
   fn to_mssql_param(&self) -> Result<&dyn mssql::ToSql> {
     Ok(self)
   }
   #[cfg(feature = "pgsql")]
   fn to_pgsql_param(&self) -> Result<&(dyn pgsql::types::ToSql + Sync)> {
-    Ok(self)
+    Ok(Adapted::ref_cast(self))
   }
-}
-
-impl UnifiedToSql for String {
   #[cfg(feature = "mssql")]
-  fn to_mssql_param(&self) -> Result<&dyn mssql::ToSql> {
-    Ok(self)
-  }
-  #[cfg(feature = "pgsql")]
-  fn to_pgsql_param(&self) -> Result<&(dyn pgsql::types::ToSql + Sync)> {
-    Ok(self)
+  fn mssql_null() -> &'static dyn mssql::ToSql {
+    &None::<u8>
   }
 }
 
-impl UnifiedToSql for Option<String> {
+impl UnifiedToSql for DateTime<Local> {
   #[cfg(feature = "mssql")]
   fn to_mssql_param(&self) -> Result<&dyn mssql::ToSql> {
-    Ok(self)
+    Ok(Adapted::ref_cast(self))
   }
   #[cfg(feature = "pgsql")]
   fn to_pgsql_param(&self) -> Result<&(dyn pgsql::types::ToSql + Sync)> {
     Ok(self)
   }
-}
-
-impl UnifiedToSql for uuid::Uuid {
   #[cfg(feature = "mssql")]
-  fn to_mssql_param(&self) -> Result<&dyn mssql::ToSql> {
-    Ok(self)
-  }
-  #[cfg(feature = "pgsql")]
-  fn to_pgsql_param(&self) -> Result<&(dyn pgsql::types::ToSql + Sync)> {
-    Ok(self)
+  fn mssql_null() -> &'static dyn mssql::ToSql {
+    &None::<DateTime<FixedOffset>>
   }
 }
 
-impl UnifiedToSql for Option<uuid::Uuid> {
+#[cfg(any(feature = "mssql", feature = "pgsql"))]
+impl UnifiedToSql for Value {
   #[cfg(feature = "mssql")]
   fn to_mssql_param(&self) -> Result<&dyn mssql::ToSql> {
-    Ok(self)
+    Err(json_unbound())
   }
   #[cfg(feature = "pgsql")]
   fn to_pgsql_param(&self) -> Result<&(dyn pgsql::types::ToSql + Sync)> {
     Ok(self)
   }
-}
-
-impl UnifiedToSql for bool {
   #[cfg(feature = "mssql")]
-  fn to_mssql_param(&self) -> Result<&dyn mssql::ToSql> {
-    Ok(self)
-  }
-  #[cfg(feature = "pgsql")]
-  fn to_pgsql_param(&self) -> Result<&(dyn pgsql::types::ToSql + Sync)> {
-    Ok(self)
+  fn mssql_json(&self) -> Option<Result<String>> {
+    Some(json_text(self))
   }
 }
 
-impl UnifiedToSql for Option<bool> {
+#[cfg(any(feature = "mssql", feature = "pgsql"))]
+impl<T> UnifiedToSql for Json<T>
+where
+  T: serde::Serialize + std::fmt::Debug + Send + Sync,
+{
   #[cfg(feature = "mssql")]
   fn to_mssql_param(&self) -> Result<&dyn mssql::ToSql> {
-    Ok(self)
+    Err(json_unbound())
   }
   #[cfg(feature = "pgsql")]
   fn to_pgsql_param(&self) -> Result<&(dyn pgsql::types::ToSql + Sync)> {
     Ok(self)
   }
-}
-
-impl UnifiedToSql for NaiveDate {
   #[cfg(feature = "mssql")]
-  fn to_mssql_param(&self) -> Result<&dyn mssql::ToSql> {
-    Ok(self)
-  }
-  #[cfg(feature = "pgsql")]
-  fn to_pgsql_param(&self) -> Result<&(dyn pgsql::types::ToSql + Sync)> {
-    Ok(self)
+  fn mssql_json(&self) -> Option<Result<String>> {
+    Some(json_text(&self.0))
   }
 }
 
-impl UnifiedToSql for Option<NaiveDate> {
-  #[cfg(feature = "mssql")]
-  fn to_mssql_param(&self) -> Result<&dyn mssql::ToSql> {
-    Ok(self)
-  }
-  #[cfg(feature = "pgsql")]
-  fn to_pgsql_param(&self) -> Result<&(dyn pgsql::types::ToSql + Sync)> {
-    Ok(self)
-  }
+/// JSON text of a parameter bound on MSSQL
+#[cfg(feature = "mssql")]
+fn json_text<T: serde::Serialize + ?Sized>(value: &T) -> Result<String> {
+  serde_json::to_string(value).map_err(|e| anyhow::anyhow!("Invalid JSON parameter: {}", e))
 }
 
-impl UnifiedToSql for NaiveTime {
-  #[cfg(feature = "mssql")]
-  fn to_mssql_param(&self) -> Result<&dyn mssql::ToSql> {
-    Ok(self)
-  }
-  #[cfg(feature = "pgsql")]
-  fn to_pgsql_param(&self) -> Result<&(dyn pgsql::types::ToSql + Sync)> {
-    Ok(self)
-  }
+#[cfg(feature = "mssql")]
+fn json_unbound() -> anyhow::Error {
+  anyhow::anyhow!("JSON parameters are bound on MSSQL from their `mssql_json` text")
 }
 
-impl UnifiedToSql for Option<NaiveTime> {
-  #[cfg(feature = "mssql")]
-  fn to_mssql_param(&self) -> Result<&dyn mssql::ToSql> {
-    Ok(self)
-  }
-  #[cfg(feature = "pgsql")]
-  fn to_pgsql_param(&self) -> Result<&(dyn pgsql::types::ToSql + Sync)> {
-    Ok(self)
-  }
+/// MSSQL JSON text of the parameters that have one, see [`UnifiedToSql::mssql_json`]
+#[cfg(feature = "mssql")]
+pub(crate) fn mssql_json_texts(params: &[&dyn UnifiedToSql]) -> Result<Vec<Option<String>>> {
+  params
+    .iter()
+    .map(|param| param.mssql_json().transpose())
+    .collect()
 }
 
-impl UnifiedToSql for DateTime<Utc> {
-  #[cfg(feature = "mssql")]
-  fn to_mssql_param(&self) -> Result<&dyn mssql::ToSql> {
-    Ok(self)
-  }
-  #[cfg(feature = "pgsql")]
-  fn to_pgsql_param(&self) -> Result<&(dyn pgsql::types::ToSql + Sync)> {
-    Ok(self)
-  }
+/// Parameters as bound on MSSQL, JSON ones as their text from [`mssql_json_texts`]
+#[cfg(feature = "mssql")]
+pub(crate) fn mssql_params<'a>(
+  params: &[&'a dyn UnifiedToSql],
+  texts: &'a [Option<String>],
+) -> Result<Vec<&'a dyn mssql::ToSql>> {
+  params
+    .iter()
+    .zip(texts)
+    .map(|(param, text)| match text {
+      Some(text) => Ok(text as &dyn mssql::ToSql),
+      None => param.to_mssql_param(),
+    })
+    .collect()
 }
 
-impl UnifiedToSql for Option<DateTime<Utc>> {
+#[cfg(test)]
+mod tests {
+  use super::*;
+
   #[cfg(feature = "mssql")]
-  fn to_mssql_param(&self) -> Result<&dyn mssql::ToSql> {
-    Ok(self)
+  fn mssql(param: &dyn UnifiedToSql) -> mssql::ColumnData<'_> {
+    param.to_mssql_param().unwrap().to_sql()
   }
-  #[cfg(feature = "pgsql")]
-  fn to_pgsql_param(&self) -> Result<&(dyn pgsql::types::ToSql + Sync)> {
-    Ok(self)
+
+  #[cfg(feature = "mssql")]
+  #[test]
+  fn mssql_small_integers() {
+    assert_eq!(mssql(&-5i8), mssql::ColumnData::I16(Some(-5)));
+    assert_eq!(mssql(&None::<i8>), mssql::ColumnData::I16(None));
+    assert_eq!(mssql(&200u8), mssql::ColumnData::U8(Some(200)));
+    assert_eq!(mssql(&None::<u8>), mssql::ColumnData::U8(None));
   }
-}
 
-impl UnifiedToSql for NaiveDateTime {
   #[cfg(feature = "mssql")]
-  fn to_mssql_param(&self) -> Result<&dyn mssql::ToSql> {
-    Ok(self)
+  #[test]
+  fn mssql_nulls_keep_their_type() {
+    use mssql::ColumnData;
+
+    let cases: [(&dyn UnifiedToSql, ColumnData); 14] = [
+      (&None::<bool>, ColumnData::Bit(None)),
+      (&None::<i16>, ColumnData::I16(None)),
+      (&None::<i32>, ColumnData::I32(None)),
+      (&None::<i64>, ColumnData::I64(None)),
+      (&None::<f32>, ColumnData::F32(None)),
+      (&None::<f64>, ColumnData::F64(None)),
+      (&None::<Decimal>, ColumnData::Numeric(None)),
+      (&None::<String>, ColumnData::String(None)),
+      (&None::<Vec<u8>>, ColumnData::Binary(None)),
+      (&None::<uuid::Uuid>, ColumnData::Guid(None)),
+      (&None::<NaiveDate>, ColumnData::Date(None)),
+      (&None::<NaiveDateTime>, ColumnData::DateTime2(None)),
+      (
+        &None::<DateTime<FixedOffset>>,
+        ColumnData::DateTimeOffset(None),
+      ),
+      (&None::<DateTime<Local>>, ColumnData::DateTimeOffset(None)),
+    ];
+    for (param, expected) in cases {
+      assert_eq!(mssql(param), expected);
+    }
+    // Through a reference and nested options as well
+    assert_eq!(mssql(&&None::<i32>), ColumnData::I32(None));
+    assert_eq!(mssql(&Some(None::<i64>)), ColumnData::I64(None));
   }
+
   #[cfg(feature = "pgsql")]
-  fn to_pgsql_param(&self) -> Result<&(dyn pgsql::types::ToSql + Sync)> {
-    Ok(self)
+  fn pgsql(param: &dyn UnifiedToSql, ty: &pgsql::types::Type) -> Result<Option<Vec<u8>>> {
+    let mut out = bytes::BytesMut::new();
+    match param
+      .to_pgsql_param()?
+      .to_sql_checked(ty, &mut out)
+      .map_err(|e| anyhow::anyhow!(e))?
+    {
+      pgsql::types::IsNull::Yes => Ok(None),
+      pgsql::types::IsNull::No => Ok(Some(out.to_vec())),
+    }
   }
-}
 
-impl UnifiedToSql for Option<NaiveDateTime> {
-  #[cfg(feature = "mssql")]
-  fn to_mssql_param(&self) -> Result<&dyn mssql::ToSql> {
-    Ok(self)
+  #[cfg(feature = "pgsql")]
+  #[test]
+  fn pgsql_small_integers_are_widened() {
+    use pgsql::types::Type;
+
+    assert_eq!(pgsql(&200u8, &Type::INT2).unwrap(), Some(vec![0, 200]));
+    assert_eq!(
+      pgsql(&200u8, &Type::INT4).unwrap(),
+      Some(vec![0, 0, 0, 200])
+    );
+    assert_eq!(pgsql(&-1i8, &Type::INT8).unwrap(), Some(vec![0xff; 8]));
+    assert!(pgsql(&1u8, &Type::TEXT).is_err());
+    assert!(pgsql(&1i8, &Type::CHAR).is_err());
   }
+
   #[cfg(feature = "pgsql")]
-  fn to_pgsql_param(&self) -> Result<&(dyn pgsql::types::ToSql + Sync)> {
-    Ok(self)
+  #[test]
+  fn pgsql_nulls_fit_any_type() {
+    use pgsql::types::Type;
+
+    for ty in [
+      Type::INT4,
+      Type::TEXT,
+      Type::BYTEA,
+      Type::TIMESTAMPTZ,
+      Type::JSONB,
+    ] {
+      assert_eq!(pgsql(&None::<i32>, &ty).unwrap(), None);
+      assert_eq!(pgsql(&None::<String>, &ty).unwrap(), None);
+    }
   }
-}
 
-impl UnifiedToSql for Value {
   #[cfg(feature = "mssql")]
-  fn to_mssql_param(&self) -> Result<&dyn mssql::ToSql> {
-    todo!("Not implemented for MSSQL yet");
+  #[test]
+  fn mssql_arrays_are_json_text_for_openjson() {
+    let ids = vec![1i32, 2, 3];
+    let names: &[&str] = &["a", "b\"c"];
+    let params: [&dyn UnifiedToSql; 3] = [&ids, &names, &Some(&ids)];
+    let texts = mssql_json_texts(&params).unwrap();
+    let bound = mssql_params(&params, &texts).unwrap();
+    let strings: Vec<_> = bound.iter().map(|param| param.to_sql()).collect();
+    assert_eq!(
+      strings,
+      [
+        mssql::ColumnData::String(Some("[1,2,3]".into())),
+        mssql::ColumnData::String(Some(r#"["a","b\"c"]"#.into())),
+        mssql::ColumnData::String(Some("[1,2,3]".into())),
+      ]
+    );
+    assert!(ids.to_mssql_param().is_err());
   }
+
   #[cfg(feature = "pgsql")]
-  fn to_pgsql_param(&self) -> Result<&(dyn pgsql::types::ToSql + Sync)> {
-    Ok(self)
+  #[test]
+  fn pgsql_arrays_bind_to_any() {
+    use pgsql::types::Type;
+
+    // `WHERE id = ANY($1)` infers an `int4[]` parameter
+    let ids = vec![1i32, 2, 3];
+    let slice: &[i32] = &ids;
+    assert!(pgsql(&ids, &Type::INT4_ARRAY).unwrap().is_some());
+    assert!(pgsql(&slice, &Type::INT4_ARRAY).unwrap().is_some());
+    assert!(pgsql(&vec!["a", "b"], &Type::TEXT_ARRAY).unwrap().is_some());
+    assert!(pgsql(&ids, &Type::INT4).is_err());
+    assert!(pgsql(&ids, &Type::INT8_ARRAY).is_err());
   }
 }
@@ -0,0 +1,52 @@
+use super::{Result, UnifiedToSql};
+use crate::types::sql::mssql;
+#[cfg(feature = "pgsql")]
+use crate::types::sql::pgsql;
+
+/// Rows for a stored procedure parameter of a user-defined table type on MSSQL.
+///
+/// Its placeholder is replaced by a table variable of `type_name` that is filled from the rows,
+/// one placeholder per value, before the statement runs. Values are inserted in the column order
+/// of the table type, e.g. for `CREATE TYPE dbo.OrderLine AS TABLE (sku NVARCHAR(20), qty INT)`:
+///
+/// ```ignore
+/// let lines: &[&[&dyn UnifiedToSql]] = &[&[&"A-1", &2], &[&"B-7", &1]];
+/// let tvp = TableValuedParam::new("dbo.OrderLine", lines);
+/// SqlRepo::execute_command_none_query(&mut client, "dbo.PlaceOrder", &[&order_id, &tvp],
+///   CommandType::StoreProcedure).await?;
+/// ```
+///
+/// Every value is a parameter of its own, so rows × columns plus the statement's other
+/// parameters must stay within the 2098 parameters of a SQL Server statement, e.g. 1000 rows of
+/// two columns. Larger sets have to be split over several calls.
+#[derive(Clone, Copy)]
+pub struct TableValuedParam<'a> {
+  /// Name of the table type, optionally schema-qualified: `dbo.OrderLine`, or
+  /// `[dbo].[Order Line]` for names that are not plain identifiers. Any other name is rejected.
+  pub type_name: &'a str,
+  pub rows: &'a [&'a [&'a dyn UnifiedToSql]],
+}
+
+impl<'a> TableValuedParam<'a> {
+  pub fn new(type_name: &'a str, rows: &'a [&'a [&'a dyn UnifiedToSql]]) -> Self {
+    Self { type_name, rows }
+  }
+}
+
+impl UnifiedToSql for TableValuedParam<'_> {
+  fn to_mssql_param(&self) -> Result<&dyn mssql::ToSql> {
+    Err(anyhow::anyhow!(
+      "A `TableValuedParam` can only be bound in the text of a command or query"
+    ))
+  }
+  #[cfg(feature = "pgsql")]
+  fn to_pgsql_param(&self) -> Result<&(dyn pgsql::types::ToSql + Sync)> {
+    Err(anyhow::anyhow!(
+      "Table-valued parameters are only supported on MSSQL"
+    ))
+  }
+
+  fn table_valued(&self) -> Option<&TableValuedParam<'_>> {
+    Some(self)
+  }
+}
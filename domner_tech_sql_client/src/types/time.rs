@@ -0,0 +1,69 @@
+//! Parameters of the `time` crate types. Reading them from rows goes through the drivers' own
+//! `time` support, enabled by the same feature.
+
+use ::time::{Date, OffsetDateTime, PrimitiveDateTime, Time};
+
+use super::{Result, UnifiedToSql};
+#[cfg(feature = "mssql")]
+use crate::types::sql::mssql;
+#[cfg(feature = "pgsql")]
+use crate::types::sql::pgsql;
+
+// `OffsetDateTime` is `datetimeoffset` on MSSQL and `timestamptz` on PostgreSQL
+unified_to_sql!(
+  Date => Date,
+  Time => Time,
+  PrimitiveDateTime => PrimitiveDateTime,
+  OffsetDateTime => OffsetDateTime,
+);
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use ::time::{Month, UtcOffset};
+
+  /// 2000-01-01 02:00 at +02:00, the PostgreSQL epoch
+  fn pg_epoch_plus_two() -> OffsetDateTime {
+    Date::from_calendar_date(2000, Month::January, 1)
+      .unwrap()
+      .with_hms(2, 0, 0)
+      .unwrap()
+      .assume_offset(UtcOffset::from_hms(2, 0, 0).unwrap())
+  }
+
+  #[cfg(feature = "mssql")]
+  #[test]
+  fn offset_date_times_are_datetimeoffset_on_mssql() {
+    let value = pg_epoch_plus_two();
+    let param = value.to_mssql_param().unwrap();
+    assert!(matches!(
+      param.to_sql(),
+      mssql::ColumnData::DateTimeOffset(Some(_))
+    ));
+    assert_eq!(
+      None::<OffsetDateTime>.to_mssql_param().unwrap().to_sql(),
+      mssql::ColumnData::DateTimeOffset(None)
+    );
+    assert_eq!(
+      None::<Date>.to_mssql_param().unwrap().to_sql(),
+      mssql::ColumnData::Date(None)
+    );
+  }
+
+  #[cfg(feature = "pgsql")]
+  #[test]
+  fn offset_date_times_are_timestamptz_on_pgsql() {
+    use pgsql::types::{IsNull, Type};
+
+    let value = pg_epoch_plus_two();
+    let param = value.to_pgsql_param().unwrap();
+    let mut out = bytes::BytesMut::new();
+    assert!(matches!(
+      param.to_sql_checked(&Type::TIMESTAMPTZ, &mut out),
+      Ok(IsNull::No)
+    ));
+    // Microseconds since 2000-01-01 00:00 UTC
+    assert_eq!(&out[..], 0i64.to_be_bytes());
+    assert!(param.to_sql_checked(&Type::TEXT, &mut out).is_err());
+  }
+}